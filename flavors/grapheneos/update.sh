@@ -5,19 +5,11 @@ set -o pipefail
 
 repo-tool get-graphene-devices -c stable -c beta -c alpha devices.json channel_info.json
 tags=$(jq -r .git_tags[] channel_info.json | sort -r)
-latest_tag=$(jq -r .git_tags[-1] channel_info.json)
 
 echo Tags to fetch: $tags
 for tag in $tags; do
-  if [ ! -e $tag.lock ]; then
-    echo No lockfile for $tag yet.
-    if [ -e $latest_tag.lock ]; then
-      echo Copying from latest tag $latest_tag.
-      cp $latest_tag.lock $tag.lock
-    fi
-  fi
   echo Fetching lockfile for tag $tag.
-  repo-tool fetch --tag -r $tag https://github.com/GrapheneOS/platform_manifest $tag.lock
+  repo-tool fetch --cache-search-path . --tag -r $tag https://github.com/GrapheneOS/platform_manifest $tag.lock
   lockfiles="$lockfiles $tag.lock"
 done
 
@@ -47,7 +39,7 @@ for lockfile in $(ls *.lock); do
 	else
 		echo "," >> yarn_hashes.json.part
 	fi
-	adevtool_path=$(jq -r '.entries.["vendor/adevtool"].lock.path' $lockfile)
+	adevtool_path=$(repo-tool query $lockfile vendor/adevtool --field store-path)
 	echo Ensuring that $adevtool_path is present in the Nix store...
 	repo-tool ensure-store-paths $lockfile vendor/adevtool
 	echo $lockfile: Prefetching yarn deps in $adevtool_path/yarn.lock
@@ -64,6 +56,6 @@ for lockfile in $(ls *.lock); do
 	git_tag=$(basename -s .lock $lockfile)
 	devices=$(jq -r ".device_info.stable | map_values(select(.git_tag == \"$git_tag\")) | keys | .[]" channel_info.json)
 	repo-tool ensure-store-paths $lockfile vendor/adevtool
-	adevtool_path=$(jq -r '.entries.["vendor/adevtool"].lock.path' $lockfile)
+	adevtool_path=$(repo-tool query $lockfile vendor/adevtool --field store-path)
 	repo-tool get-graphene-vendor-img-metadata $adevtool_path vendor_img_metadata_$git_tag.json $devices
 done
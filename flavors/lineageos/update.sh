@@ -12,5 +12,5 @@ for version in 22.2 22.1 21.0 20.0 19.1 18.1 17.1; do
 	  --muppets \
 	  https://github.com/LineageOS/android \
 	  lineage-$version/repo.lock \
-	  -m lineage-$version/missing_dep_devices.json
+	  --missing-dep-devs-file lineage-$version/missing_dep_devices.json
 done
@@ -6,12 +6,11 @@ let
   inherit (lib)
     mkDefault
     mkIf
-    mkMerge
     mkBefore
   ;
-  repoDirs = lib.importJSON ./repo-lineage-17.1.json;
+  lockfile = ./repo-lineage-17.1.lock;
   patchMetadata = lib.importJSON ./patch-metadata.json;
-  repoDateTimes = lib.mapAttrsToList (name: value: value.dateTime) repoDirs;
+  repoDateTimes = lib.mapAttrsToList (name: entry: entry.lock.date) (lib.importJSON lockfile).entries;
   maxRepoDateTime = lib.foldl (a: b: lib.max a b) 0 repoDateTimes;
 in mkIf (config.flavor == "waydroid")
 {
@@ -21,12 +20,14 @@ in mkIf (config.flavor == "waydroid")
   productNamePrefix = "lineage_waydroid_";
   variant = mkDefault "userdebug";
 
-  source.dirs = mkMerge [
-    repoDirs
-    (lib.mapAttrs (relpath: patches: {
-      gitPatches = (builtins.map (p: "${config.source.dirs."vendor/extra".src}/${patches.dir}/${p}") patches.files);
-    }) patchMetadata)
-  ];
+  source.manifest = {
+    enable = true;
+    inherit lockfile;
+  };
+
+  source.dirs = lib.mapAttrs (relpath: patches: {
+    gitPatches = (builtins.map (p: "${config.source.dirs."vendor/extra".src}/${patches.dir}/${p}") patches.files);
+  }) patchMetadata;
 
   envVars.RELEASE_TYPE = mkDefault "EXPERIMENTAL";  # Other options are RELEASE NIGHTLY SNAPSHOT EXPERIMENTAL
 
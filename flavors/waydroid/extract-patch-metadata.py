@@ -7,9 +7,9 @@ from robotnix_common import checkout_git, save
 
 
 def main() -> None:
-    data = json.load(open('repo-lineage-17.1.json'))
-    waydroid_vendor = data['vendor/extra']
-    git_info = checkout_git(waydroid_vendor['url'], waydroid_vendor['rev'])
+    data = json.load(open('repo-lineage-17.1.lock'))
+    waydroid_vendor = data['entries']['vendor/extra']
+    git_info = checkout_git(waydroid_vendor['project']['repo_ref']['repo_url'], waydroid_vendor['lock']['commit'])
     topdir = git_info['path']
 
     output = {}
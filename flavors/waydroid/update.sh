@@ -26,3 +26,7 @@ done
 export TMPDIR=/tmp
 
 ../../scripts/mk_repo_file.py "${args[@]}"
+
+# Convert the legacy repo file into a lockfile for `source.manifest.lockfile`.
+repo-tool import-legacy repo-lineage-17.1.json repo-lineage-17.1.lock
+rm repo-lineage-17.1.json
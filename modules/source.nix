@@ -6,6 +6,8 @@
 let
   inherit (lib) mkIf mkDefault mkMerge mkOption types;
 
+  isNixLockfile = lib.hasSuffix ".nix" (toString config.source.manifest.lockfile);
+
   # A tree (attrset containing attrsets) which matches the source directories relpath filesystem structure.
   # e.g.
   # {
@@ -187,7 +189,8 @@ in
           type = types.nullOr types.path;
           default = null;
           description = ''
-            git-repo manifest lockfile as generated by `repo2nix fetch`.
+            git-repo manifest lockfile as generated by `repo2nix fetch`, or a `.nix` file
+            exported from it with `repo-tool export-nix`.
           '';
         };
 
@@ -225,11 +228,19 @@ in
     manifest.categories = [ "Default" ];
     dirs = mkIf config.source.manifest.enable (
       let
-        entries = (lib.importJSON config.source.manifest.lockfile).entries;
-        filteredEntries = lib.filterAttrs (
-          path: entry: entry.project.active && (builtins.any (cat: builtins.elem cat entry.project.categories) config.source.manifest.categories)
-        ) entries;
-        dirs = lib.mapAttrs (path: entry: {
+        hasCategory = categories: builtins.any (cat: builtins.elem cat categories) config.source.manifest.categories;
+        # Lockfiles exported with `repo-tool export-nix` only contain active projects, and
+        # already have the `fetchgit` arguments in place.
+        nixDirs = lib.mapAttrs (path: project: {
+          manifestSrc = pkgs.fetchgit project.fetchgit;
+          inherit (project) groups linkfiles copyfiles date;
+        }) (lib.filterAttrs (path: project: hasCategory project.categories) (import config.source.manifest.lockfile).projects);
+        jsonDirs = let
+          entries = (lib.importJSON config.source.manifest.lockfile).entries;
+          filteredEntries = lib.filterAttrs (
+            path: entry: entry.project.active && hasCategory entry.project.categories
+          ) entries;
+        in lib.mapAttrs (path: entry: {
           manifestSrc = pkgs.fetchgit {
             url = entry.project.repo_ref.repo_url;
             rev = entry.lock.commit;
@@ -240,13 +251,17 @@ in
           inherit (entry.project) groups linkfiles copyfiles;
           inherit (entry.lock) date;
         }) filteredEntries;
-      in dirs);
+      in if isNixLockfile then nixDirs else jsonDirs);
   };
 
   config = {
     assertions = [
       {
-        assertion = config.source.manifest.enable -> (lib.importJSON config.source.manifest.lockfile).fetch_completed;
+        assertion = config.source.manifest.enable -> (
+          if isNixLockfile
+          then (import config.source.manifest.lockfile).fetchCompleted
+          else (lib.importJSON config.source.manifest.lockfile).fetch_completed
+        );
         message = "The git-repo lockfile set via `source.manifest.lockfile` is marked as incomplete. Try rerunning `repo fetch` on it.";
       }
     ];
@@ -0,0 +1,351 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use thiserror::Error;
+use url::Url;
+
+/// A git remote URL in any of the forms git itself understands.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GitUrl {
+    /// `<scheme>://[<user>@]<host>[:<port>]/<path>` URLs, e.g. `https`, `ssh` or `file` URLs.
+    Url(Url),
+    /// scp-like `[<user>@]<host>:<path>` syntax.
+    Scp {
+        user: Option<String>,
+        host: String,
+        path: String,
+    },
+    /// An absolute path to a repository on the local filesystem.
+    Local(String),
+}
+
+#[derive(Debug, Error)]
+pub enum ParseGitUrlError {
+    #[error("couldn't parse URL `{0}`")]
+    Url(String, #[source] url::ParseError),
+    #[error("`{0}` is a relative URL")]
+    Relative(String),
+    #[error("empty host in URL `{0}`")]
+    EmptyHost(String),
+}
+
+impl GitUrl {
+    /// Parses an absolute git URL, following git's rules for telling apart URLs, scp-like
+    /// addresses and local paths.
+    pub fn parse(text: &str) -> Result<Self, ParseGitUrlError> {
+        if text.contains("://") {
+            return Url::parse(text)
+                .map(GitUrl::Url)
+                .map_err(|e| ParseGitUrlError::Url(text.to_string(), e));
+        }
+
+        // Like git, treat everything with a colon before the first slash as scp-like.
+        if let Some(colon) = text.find(':') {
+            if text.find('/').is_none_or(|slash| colon < slash) {
+                let (user_host, path) = (&text[..colon], &text[colon + 1..]);
+                let (user, host) = match user_host.rsplit_once('@') {
+                    Some((user, host)) => (Some(user.to_string()), host.to_string()),
+                    None => (None, user_host.to_string()),
+                };
+                if host.is_empty() {
+                    return Err(ParseGitUrlError::EmptyHost(text.to_string()));
+                }
+                return Ok(GitUrl::Scp { user, host, path: path.to_string() });
+            }
+        }
+
+        if text.starts_with('/') {
+            Ok(GitUrl::Local(text.to_string()))
+        } else {
+            Err(ParseGitUrlError::Relative(text.to_string()))
+        }
+    }
+
+    /// The path (on the host) of the repository.
+    pub fn path(&self) -> &str {
+        match self {
+            GitUrl::Url(url) => url.path(),
+            GitUrl::Scp { path, .. } => path,
+            GitUrl::Local(path) => path,
+        }
+    }
+
+    /// The host of the repository, if it isn't a local one.
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            GitUrl::Url(url) => url.host_str().filter(|x| !x.is_empty()),
+            GitUrl::Scp { host, .. } => Some(host),
+            GitUrl::Local(_) => None,
+        }
+    }
+
+    /// The local filesystem path of the repository, for absolute paths and `file://` URLs.
+    pub fn to_local_path(&self) -> Option<PathBuf> {
+        match self {
+            GitUrl::Url(url) if url.scheme() == "file" => url.to_file_path().ok(),
+            GitUrl::Local(path) => Some(PathBuf::from(path)),
+            _ => None,
+        }
+    }
+
+    fn with_path(&self, path: &str) -> Self {
+        match self {
+            GitUrl::Url(url) => {
+                let mut url = url.clone();
+                url.set_path(path);
+                GitUrl::Url(url)
+            },
+            GitUrl::Scp { user, host, .. } => GitUrl::Scp {
+                user: user.clone(),
+                host: host.clone(),
+                path: path.to_string(),
+            },
+            GitUrl::Local(_) => GitUrl::Local(path.to_string()),
+        }
+    }
+
+    /// Appends a repository name to a remote URL the way git-repo does, i.e. separated by exactly
+    /// one slash.
+    pub fn join(&self, repo_name: &str) -> Self {
+        let base = self.path().trim_end_matches('/');
+        let repo_name = repo_name.trim_start_matches('/');
+        let path = match self {
+            // `host:` + `repo` should be `host:repo` and not `host:/repo`, which is an absolute path
+            // on the host.
+            GitUrl::Scp { .. } if base.is_empty() => repo_name.to_string(),
+            _ => format!("{base}/{repo_name}"),
+        };
+        self.with_path(&path)
+    }
+
+    /// Resolves a (possibly relative) remote `fetch` attribute against this manifest URL,
+    /// following git-repo's `_resolveFetchUrl`: trailing slashes are stripped from both, and the
+    /// result is what Python's `urljoin` makes of them. Manifest URLs whose first colon isn't
+    /// followed by a slash (e.g. scp-like ones) are joined as if they were `gopher://` URLs. Like
+    /// git-repo, `ssh://`, `git://` and a few other git schemes support relative URLs as well.
+    pub fn resolve_relative(&self, relative: &str) -> Result<Self, ParseGitUrlError> {
+        let relative = relative.trim_end_matches('/');
+        let manifest_url = self.to_string();
+        let manifest_url = manifest_url.trim_end_matches('/');
+
+        // Python's `str.find` returns -1 if there is no match.
+        let find = |x| manifest_url.find(x).map_or(-1, |i| i as isize);
+        let resolved = if find(':') != find('/') - 1 {
+            let resolved = urljoin(&format!("gopher://{manifest_url}"), relative);
+            resolved.strip_prefix("gopher://").map(str::to_string).unwrap_or(resolved)
+        } else {
+            urljoin(manifest_url, relative)
+        };
+        GitUrl::parse(&resolved)
+    }
+}
+
+/// The schemes Python's `urljoin` resolves relative URLs for, including the ones git-repo adds to
+/// `uses_relative` and `uses_netloc`.
+const USES_RELATIVE: &[&str] = &[
+    "", "ftp", "http", "gopher", "nntp", "imap", "wais", "file", "https", "shttp", "mms",
+    "prospero", "rtsp", "rtspu", "sftp", "svn", "svn+ssh", "ws", "wss",
+    "ssh", "git", "persistent-https", "sso", "rpc",
+];
+
+/// Splits a URL into scheme, network location and path like Python's `urlsplit`, using `scheme`
+/// if the URL has none. Query strings and fragments, which remote URLs don't have, are left in
+/// the path.
+fn urlsplit<'a>(url: &'a str, scheme: &'a str) -> (&'a str, Option<&'a str>, &'a str) {
+    let (scheme, rest) = match url.split_once(':') {
+        Some((prefix, rest)) if prefix.starts_with(|x: char| x.is_ascii_alphabetic())
+            && prefix.chars().all(|x| x.is_ascii_alphanumeric() || "+-.".contains(x)) => (prefix, rest),
+        _ => (scheme, url),
+    };
+    match rest.strip_prefix("//") {
+        Some(rest) => {
+            let (netloc, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            (scheme, Some(netloc), path)
+        },
+        None => (scheme, None, rest),
+    }
+}
+
+/// Resolves `url` against `base` like Python's `urllib.parse.urljoin`.
+fn urljoin(base: &str, url: &str) -> String {
+    if url.is_empty() {
+        return base.to_string();
+    }
+
+    let (base_scheme, base_netloc, base_path) = urlsplit(base, "");
+    let (scheme, netloc, path) = urlsplit(url, base_scheme);
+    if scheme != base_scheme || !USES_RELATIVE.contains(&scheme) {
+        return url.to_string();
+    }
+    let (netloc, path) = match netloc {
+        Some(netloc) => (Some(netloc), path.to_string()),
+        None => (base_netloc, urljoin_path(base_path, path)),
+    };
+
+    match (scheme, netloc) {
+        ("", None) => path,
+        ("", Some(netloc)) => format!("//{netloc}{path}"),
+        (scheme, netloc) => format!("{scheme}://{}{path}", netloc.unwrap_or_default()),
+    }
+}
+
+/// Resolves the path of a relative URL against a base path like Python's `urljoin`.
+fn urljoin_path(base: &str, relative: &str) -> String {
+    if relative.is_empty() {
+        return base.to_string();
+    }
+
+    let mut segments: Vec<_> = if relative.starts_with('/') {
+        relative.split('/').collect()
+    } else {
+        let mut base_segments: Vec<_> = base.split('/').collect();
+        if base_segments.last() != Some(&"") {
+            base_segments.pop();
+        }
+        let mut segments = base_segments;
+        segments.extend(relative.split('/'));
+        // Empty segments in the middle are dropped, only the leading and trailing ones are kept.
+        let last = segments.len() - 1;
+        segments
+            .iter()
+            .enumerate()
+            .filter(|(i, x)| *i == 0 || *i == last || !x.is_empty())
+            .map(|(_, x)| *x)
+            .collect()
+    };
+
+    let trailing_dot = matches!(segments.last(), Some(&".") | Some(&".."));
+    let mut resolved = vec![];
+    for segment in segments.drain(..) {
+        match segment {
+            ".." => {
+                resolved.pop();
+            },
+            "." => (),
+            _ => resolved.push(segment),
+        }
+    }
+    if trailing_dot {
+        resolved.push("");
+    }
+
+    let path = resolved.join("/");
+    if path.is_empty() {
+        "/".to_string()
+    } else {
+        path
+    }
+}
+
+impl fmt::Display for GitUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitUrl::Url(url) => write!(f, "{url}"),
+            GitUrl::Scp { user: Some(user), host, path } => write!(f, "{user}@{host}:{path}"),
+            GitUrl::Scp { user: None, host, path } => write!(f, "{host}:{path}"),
+            GitUrl::Local(path) => write!(f, "{path}"),
+        }
+    }
+}
+
+impl FromStr for GitUrl {
+    type Err = ParseGitUrlError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        GitUrl::parse(text)
+    }
+}
+
+impl Serialize for GitUrl {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for GitUrl {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        GitUrl::parse(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_git_urls() {
+        assert!(matches!(GitUrl::parse("https://github.com/LineageOS/android").unwrap(), GitUrl::Url(_)));
+        assert!(matches!(GitUrl::parse("ssh://git@example.com:2222/vendor/manifest").unwrap(), GitUrl::Url(_)));
+        assert!(matches!(GitUrl::parse("file:///srv/git/manifest").unwrap(), GitUrl::Url(_)));
+        assert_eq!(GitUrl::parse("git@example.com:vendor/manifest.git").unwrap(), GitUrl::Scp {
+            user: Some("git".to_string()),
+            host: "example.com".to_string(),
+            path: "vendor/manifest.git".to_string(),
+        });
+        assert_eq!(GitUrl::parse("/srv/git/manifest").unwrap(), GitUrl::Local("/srv/git/manifest".to_string()));
+        assert!(matches!(GitUrl::parse("../platform"), Err(ParseGitUrlError::Relative(_))));
+        assert!(matches!(GitUrl::parse("./foo:bar"), Err(ParseGitUrlError::Relative(_))));
+
+        for text in ["git@example.com:vendor/manifest.git", "example.com:manifest", "/srv/git/manifest", "ssh://git@example.com:2222/vendor"] {
+            assert_eq!(GitUrl::parse(text).unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn join_git_urls() {
+        let join = |base: &str, name: &str| GitUrl::parse(base).unwrap().join(name).to_string();
+        assert_eq!(join("https://github.com/LineageOS/", "android_build"), "https://github.com/LineageOS/android_build");
+        assert_eq!(join("https://github.com/LineageOS", "android_build"), "https://github.com/LineageOS/android_build");
+        assert_eq!(join("ssh://git@example.com:2222/vendor", "foo/bar"), "ssh://git@example.com:2222/vendor/foo/bar");
+        assert_eq!(join("git@example.com:vendor", "foo"), "git@example.com:vendor/foo");
+        assert_eq!(join("git@example.com:", "foo"), "git@example.com:foo");
+        assert_eq!(join("/srv/git/", "foo"), "/srv/git/foo");
+    }
+
+    #[test]
+    fn join_resolved_remote_urls() {
+        let join = |base: &str, name: &str| GitUrl::parse(base).unwrap().join(name).to_string();
+        assert_eq!(join("https://github.com/", "LineageOS/android_build"), "https://github.com/LineageOS/android_build");
+        assert_eq!(join("git@example.com:vendor/", "foo"), "git@example.com:vendor/foo");
+    }
+
+    #[test]
+    fn resolve_relative_remote_urls() {
+        // (manifest URL, remote fetch attribute, resolved remote URL), as resolved by git-repo
+        let cases = [
+            ("https://github.com/LineageOS/android", "..", "https://github.com/"),
+            ("https://github.com/LineageOS/android/", "..", "https://github.com/"),
+            ("https://github.com/LineageOS/android.git", "..", "https://github.com/"),
+            ("https://github.com/LineageOS/android.git/", "../", "https://github.com/"),
+            ("https://android.googlesource.com/platform/manifest", ".", "https://android.googlesource.com/platform/"),
+            ("https://host/a/b/c/manifest", "../..", "https://host/a/"),
+            ("https://host/a/b/manifest", "../..", "https://host/"),
+            ("https://host/manifest", "../../..", "https://host/"),
+            ("https://host/a/manifest", "../mirror", "https://host/mirror"),
+            ("https://host/a/manifest", "../mirror/", "https://host/mirror"),
+            ("https://host/a/manifest", "./mirror//aosp", "https://host/a/mirror/aosp"),
+            ("https://host/a/manifest", "/other", "https://host/other"),
+            ("https://host/a/manifest", "//mirror.example.com/aosp", "https://mirror.example.com/aosp"),
+            ("https://host/a/manifest", "https://android.googlesource.com", "https://android.googlesource.com/"),
+            ("https://host/a/manifest", "ssh://git@github.com", "ssh://git@github.com"),
+            ("ssh://git@host:29418/platform/manifest", "..", "ssh://git@host:29418/"),
+            ("git://host/platform/manifest", "../mirror", "git://host/mirror"),
+            ("git@host:vendor/manifest", "..", "git@host:vendor/"),
+            ("git@host:vendor/sub/manifest.git", "..", "git@host:vendor/"),
+            ("git@host:vendor/sub/manifest", ".", "git@host:vendor/sub/"),
+            ("git@host:vendor/sub/manifest", "../other", "git@host:vendor/other"),
+            ("git@host:vendor/manifest", "git@other:mirror", "git@host:vendor/git@other:mirror"),
+            ("ssh://git@host:29418/platform/manifest", "ssh://git@other/mirror", "ssh://git@other/mirror"),
+            ("file:///srv/git/platform/manifest", "..", "file:///srv/git/"),
+            ("/srv/git/platform/manifest", "..", "/srv/git/"),
+            ("/srv/git/platform/manifest/", "../mirror", "/srv/git/mirror"),
+        ];
+
+        for (manifest_url, fetch, expected) in cases {
+            let resolved = GitUrl::parse(manifest_url).unwrap().resolve_relative(fetch).unwrap();
+            assert_eq!(resolved.to_string(), expected, "resolving `{fetch}` against `{manifest_url}`");
+        }
+    }
+}
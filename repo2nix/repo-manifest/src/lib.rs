@@ -1,4 +1,5 @@
 pub mod xml;
+pub mod git_url;
 pub mod resolver;
 
 #[cfg(test)]
@@ -7,7 +8,7 @@ mod tests {
     use crate::resolver::{recursively_read_manifest_files, resolve_manifest};
     use quick_xml;
     use std::path::{Path, PathBuf};
-    use url::Url;
+    use crate::git_url::GitUrl;
     use tokio;
 
     #[tokio::test]
@@ -22,7 +23,7 @@ mod tests {
         let manifest_xml = recursively_read_manifest_files(&manifest_path, &Path::new("default.xml")).await.unwrap();
         let manifest = resolve_manifest(
             &manifest_xml,
-            &Url::parse("https://github.com/LineageOS/android/").unwrap()
+            &GitUrl::parse("https://github.com/LineageOS/android/").unwrap()
         ).unwrap();
 
         for remote in manifest.remotes.values() {
@@ -35,4 +36,20 @@ mod tests {
             println!("{}", project.repo_ref.repo_url);
         }
     }
+
+    #[tokio::test]
+    async fn resolving_relative_remotes() {
+        let manifest_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/android");
+        let manifest_xml = recursively_read_manifest_files(&manifest_path, Path::new("default.xml")).await.unwrap();
+        let manifest = resolve_manifest(
+            &manifest_xml,
+            &GitUrl::parse("https://github.com/LineageOS/android/").unwrap()
+        ).unwrap();
+
+        assert_eq!(manifest.remotes["github"].url.to_string(), "https://github.com/");
+        assert_eq!(
+            manifest.projects[Path::new("build/make")].repo_ref.repo_url.to_string(),
+            "https://github.com/LineageOS/android_build"
+        );
+    }
 }
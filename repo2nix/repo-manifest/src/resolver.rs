@@ -1,13 +1,13 @@
 use std::path::{Path, PathBuf};
 use std::collections::{HashMap, BTreeSet};
 use serde::{Serialize, Deserialize};
-use url::{Url, ParseError};
 use thiserror::Error;
 use crate::xml::{self, read_manifest_file};
+use crate::git_url::{GitUrl, ParseGitUrlError};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct GitRepoRef {
-    pub repo_url: Url,
+    pub repo_url: GitUrl,
     pub revision: String,
     pub fetch_lfs: bool,
     pub fetch_submodules: bool,
@@ -57,7 +57,7 @@ pub struct Project {
 #[derive(Debug)]
 pub struct Remote {
     pub name: String,
-    pub url: Url,
+    pub url: GitUrl,
     pub revision: Option<String>,
 }
 
@@ -138,24 +138,14 @@ pub async fn recursively_read_manifest_files(root_path: &Path, manifest_file: &P
     Ok(manifest)
 }
 
-pub fn join_repo_url(base_url: &Url, repo_name: &str) -> Url {
-    let base_path = &Path::new(base_url.path());
-    let path = base_path.join(&repo_name);
-    let mut url = base_url.clone();
-    // This unwrap should be safe, as repo_name is guaranteed to be valid UTF-8.
-    url.set_path(path.to_str().unwrap());
-
-    url
+pub fn join_repo_url(base_url: &GitUrl, repo_name: &str) -> GitUrl {
+    base_url.join(repo_name)
 }
 
 #[derive(Debug, Error)]
 pub enum ResolveManifestError {
     #[error("couldn't parse URL")]
-    ParseURL(#[from] url::ParseError),
-    #[error("invalid relative remote url")]
-    InvalidRelativeRemoteURL(String),
-    #[error("invalid UTF-8 in path")]
-    InvalidUTF8(PathBuf),
+    ParseURL(#[from] ParseGitUrlError),
     #[error("unknown remote `{0}` in <default> tag")]
     DefaultRemoteNotFound(String),
     #[error("unknown remote `{1}` for project `{0}`")]
@@ -166,7 +156,7 @@ pub enum ResolveManifestError {
     MissingRevision(String),
 }
 
-pub fn resolve_manifest(manifest_xml: &xml::Manifest, base_url: &Url) -> Result<Manifest, ResolveManifestError> {
+pub fn resolve_manifest(manifest_xml: &xml::Manifest, base_url: &GitUrl) -> Result<Manifest, ResolveManifestError> {
     let mut manifest = Manifest {
         base_url: base_url.to_string(),
         remotes: HashMap::new(),
@@ -175,18 +165,7 @@ pub fn resolve_manifest(manifest_xml: &xml::Manifest, base_url: &Url) -> Result<
     };
 
     for remote_xml in manifest_xml.remotes.iter() {
-        let url = match Url::parse(&remote_xml.fetch) {
-            Ok(u) => Ok(u),
-            Err(ParseError::RelativeUrlWithoutBase) => {
-                let base_path = &Path::new(base_url.path());
-                let path = base_path.parent().ok_or(ResolveManifestError::InvalidRelativeRemoteURL(remote_xml.fetch.clone()))?.join(&remote_xml.fetch);
-                let mut url = base_url.clone();
-                url.set_path(path.to_str().ok_or(ResolveManifestError::InvalidUTF8(path.clone()))?);
-
-                Ok(url)
-            },
-            Err(e) => Err(ResolveManifestError::ParseURL(e)),
-        }?;
+        let url = base_url.resolve_relative(&remote_xml.fetch)?;
 
         let remote = Remote {
             name: remote_xml.name.clone(),
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use thiserror::Error;
+use repo_manifest::resolver::GitRepoRef;
+use crate::lock::{
+    Lock,
+    Lockset,
+    ReadWriteLockfileError,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LockCacheKey {
+    pub repo_url: String,
+    pub commit: String,
+    pub fetch_lfs: bool,
+    pub fetch_submodules: bool,
+}
+
+impl LockCacheKey {
+    pub fn new(repo_ref: &GitRepoRef, commit: &str) -> Self {
+        LockCacheKey {
+            repo_url: repo_ref.repo_url.to_string(),
+            commit: commit.to_string(),
+            fetch_lfs: repo_ref.fetch_lfs,
+            fetch_submodules: repo_ref.fetch_submodules,
+        }
+    }
+}
+
+/// Index of already-computed locks, used to avoid prefetching a repository at a commit that some
+/// other lockfile has already locked with the same fetch options.
+#[derive(Debug, Default)]
+pub struct LockCache {
+    locks: HashMap<LockCacheKey, Lock>,
+}
+
+#[derive(Debug, Error)]
+pub enum BuildLockCacheError {
+    #[error("error searching for lockfiles in `{0}`")]
+    IO(PathBuf, #[source] io::Error),
+    #[error("error reading lockfile `{0}`")]
+    ReadLockfile(PathBuf, #[source] ReadWriteLockfileError),
+}
+
+impl LockCache {
+    pub fn get(&self, repo_ref: &GitRepoRef, commit: &str) -> Option<&Lock> {
+        self.locks.get(&LockCacheKey::new(repo_ref, commit))
+    }
+
+    pub fn insert(&mut self, repo_ref: &GitRepoRef, lock: &Lock) {
+        self.locks.insert(LockCacheKey::new(repo_ref, &lock.commit), lock.clone());
+    }
+
+    pub fn len(&self) -> usize {
+        self.locks.len()
+    }
+
+    pub fn add_lockset(&mut self, lockset: &Lockset) {
+        for entry in lockset.entries.values() {
+            if let Some(lock) = &entry.lock {
+                self.insert(&entry.project.repo_ref, lock);
+            }
+        }
+    }
+
+    /// Recursively adds the locks of all `*.lock` files below `path` (or `path` itself, if it is a
+    /// file). Files that aren't repo2nix lockfiles (e.g. `flake.lock`) are skipped.
+    pub async fn add_search_path(&mut self, path: &Path) -> Result<(), BuildLockCacheError> {
+        for lockfile_path in find_lockfiles(path).await? {
+            if let Some(lockset) = read_lockfile_if_any(&lockfile_path).await? {
+                eprintln!("Loading cached locks from `{}`", lockfile_path.display());
+                self.add_lockset(&lockset);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the lockfile at `path`, or returns `None` if it is some other kind of lockfile: a JSON
+/// document without `entries` (e.g. `flake.lock`), or not JSON at all (e.g. `Cargo.lock`).
+/// Unreadable files and damaged repo2nix lockfiles are reported as errors.
+pub async fn read_lockfile_if_any(path: &Path) -> Result<Option<Lockset>, BuildLockCacheError> {
+    let read_error = |e| BuildLockCacheError::ReadLockfile(path.to_path_buf(), e);
+    let json = fs::read(path).await.map_err(|e| read_error(ReadWriteLockfileError::IO(e)))?;
+    let is_lockfile = match serde_json::from_slice::<serde_json::Value>(&json) {
+        Ok(value) => value.get("entries").is_some(),
+        // Truncated or otherwise damaged JSON documents are most likely damaged lockfiles.
+        Err(_) => json.trim_ascii_start().starts_with(b"{"),
+    };
+    if !is_lockfile {
+        eprintln!("Skipping `{}`, not a repo2nix lockfile", path.display());
+        return Ok(None);
+    }
+
+    Lockset::read_from_file(path).await.map(Some).map_err(read_error)
+}
+
+/// Recursively finds all `*.lock` files below `path`, or returns `path` itself if it is a file.
+/// Symlinks to lockfiles are followed, symlinks to directories aren't, so that symlink cycles
+/// can't lead to infinite recursion.
+pub async fn find_lockfiles(path: &Path) -> Result<Vec<PathBuf>, BuildLockCacheError> {
+    let metadata = fs::metadata(path)
+        .await
+        .map_err(|e| BuildLockCacheError::IO(path.to_path_buf(), e))?;
+    if !metadata.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut dir = fs::read_dir(path)
+        .await
+        .map_err(|e| BuildLockCacheError::IO(path.to_path_buf(), e))?;
+    let mut children = vec![];
+    while let Some(child) = dir
+        .next_entry()
+        .await
+        .map_err(|e| BuildLockCacheError::IO(path.to_path_buf(), e))? {
+        children.push(child.path());
+    }
+    children.sort();
+
+    let mut lockfiles = vec![];
+    for child in children {
+        let metadata = fs::symlink_metadata(&child)
+            .await
+            .map_err(|e| BuildLockCacheError::IO(child.clone(), e))?;
+        if metadata.is_dir() {
+            lockfiles.extend(Box::pin(find_lockfiles(&child)).await?);
+        } else if child.extension().is_some_and(|x| x == "lock") {
+            lockfiles.push(child);
+        }
+    }
+
+    Ok(lockfiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::test_utils::{test_project, TempDir};
+
+    #[tokio::test]
+    async fn search_path_skips_other_lockfiles() {
+        let tmp = TempDir::new("lock-cache");
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let projects = HashMap::from([(PathBuf::from("build/make"), test_project("build/make", "https://android.googlesource.com/platform/build", commit))]);
+        let mut lockset = Lockset::new(&projects, &tmp.path().join("releases/a.lock"));
+        lockset.entries.get_mut(Path::new("build/make")).unwrap().lock = Some(Lock {
+            commit: commit.to_string(),
+            nix_hash: "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A=".to_string(),
+            path: PathBuf::from("/nix/store/caphp23qx36iqbpcfa0wx1q16h8sq65x-build-0123456"),
+            date: 1,
+        });
+        fs::create_dir_all(tmp.path().join("releases")).await.unwrap();
+        lockset.write(true).await.unwrap();
+        fs::write(tmp.path().join("flake.lock"), r#"{ "nodes": {}, "version": 7 }"#).await.unwrap();
+        fs::write(tmp.path().join("Cargo.lock"), "version = 4\n").await.unwrap();
+        // A symlink cycle must not be followed.
+        std::os::unix::fs::symlink(tmp.path(), tmp.path().join("releases/loop")).unwrap();
+
+        let mut cache = LockCache::default();
+        cache.add_search_path(tmp.path()).await.unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&projects[Path::new("build/make")].repo_ref, commit).is_some());
+
+        // Damaged lockfiles are reported instead of being skipped.
+        fs::write(tmp.path().join("releases/b.lock"), r#"{ "fetch_completed": true, "entries": {"#).await.unwrap();
+        assert!(matches!(
+            LockCache::default().add_search_path(tmp.path()).await,
+            Err(BuildLockCacheError::ReadLockfile(..))
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use serde::Serialize;
+use thiserror::Error;
+use repo_manifest::git_url::GitUrl;
+use crate::diff::{LockfileDiff, Change};
+use crate::git_cache::{
+    bare_repo,
+    ensure_commit,
+    git,
+    GitError,
+};
+use crate::lock::LocksetEntry;
+use crate::utils::format_date;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommitInfo {
+    pub commit: String,
+    pub subject: String,
+    pub author: String,
+    /// Author date as UNIX timestamp.
+    pub date: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectChangelog {
+    pub repo_url: GitUrl,
+    pub old_commit: String,
+    pub new_commit: String,
+    /// Commits reachable from the new but not from the old commit, newest first.
+    pub commits: Vec<CommitInfo>,
+}
+
+#[derive(Debug, Error)]
+pub enum ChangelogError {
+    #[error("error creating git cache directory")]
+    CreateCacheDir(#[source] io::Error),
+    #[error("error getting commit log of `{0}`")]
+    Git(PathBuf, #[source] GitError),
+}
+
+/// Lists the commits between `old_commit` and `new_commit`, using (and filling) a bare repo in
+/// `git_cache_dir`. The old commit is fetched from `old_url` in case it isn't part of the history
+/// of the new one.
+pub async fn commit_log(git_cache_dir: &Path, old_url: &GitUrl, old_commit: &str, new_url: &GitUrl, new_commit: &str) -> Result<Vec<CommitInfo>, GitError> {
+    let git_dir = bare_repo(git_cache_dir, new_url).await?;
+
+    ensure_commit(&git_dir, new_url, new_commit).await?;
+    ensure_commit(&git_dir, old_url, old_commit).await?;
+
+    let output = git(&git_dir, &[
+        "log",
+        "--format=%H%x00%an%x00%at%x00%s",
+        &format!("{old_commit}..{new_commit}"),
+    ]).await?;
+    let output = std::str::from_utf8(&output).map_err(|_| GitError::Parse)?;
+
+    let mut commits = vec![];
+    for line in output.lines() {
+        match line.splitn(4, '\0').collect::<Vec<_>>().as_slice() {
+            [commit, author, date, subject] => commits.push(CommitInfo {
+                commit: commit.to_string(),
+                subject: subject.to_string(),
+                author: author.to_string(),
+                date: date.parse().map_err(|_| GitError::Parse)?,
+            }),
+            _ => return Err(GitError::Parse),
+        }
+    }
+
+    Ok(commits)
+}
+
+/// Collects the commit logs of all projects whose locked commit changed between two lockfiles.
+pub async fn changelog(
+    diff: &LockfileDiff,
+    old: &BTreeMap<PathBuf, LocksetEntry>,
+    new: &BTreeMap<PathBuf, LocksetEntry>,
+    git_cache_dir: &Path
+) -> Result<BTreeMap<PathBuf, ProjectChangelog>, ChangelogError> {
+    fs::create_dir_all(git_cache_dir).await.map_err(ChangelogError::CreateCacheDir)?;
+
+    let old_paths: BTreeMap<_, _> = diff.moved.iter().map(|(old, new)| (new, old)).collect();
+    let mut changelogs = BTreeMap::new();
+    for (path, changes) in diff.changed.iter() {
+        let Some(Change { old: Some(old_commit), new: Some(new_commit) }) = &changes.commit else {
+            continue;
+        };
+        let old_path = old_paths.get(path).copied().unwrap_or(path);
+        let old_url = &old[old_path].project.repo_ref.repo_url;
+        let new_url = &new[path].project.repo_ref.repo_url;
+
+        eprintln!("Getting commit log of `{}`...", path.display());
+        let commits = commit_log(git_cache_dir, old_url, old_commit, new_url, new_commit)
+            .await
+            .map_err(|e| ChangelogError::Git(path.clone(), e))?;
+        changelogs.insert(path.clone(), ProjectChangelog {
+            repo_url: new_url.clone(),
+            old_commit: old_commit.clone(),
+            new_commit: new_commit.clone(),
+            commits,
+        });
+    }
+
+    Ok(changelogs)
+}
+
+pub fn render_markdown(changelogs: &BTreeMap<PathBuf, ProjectChangelog>) -> String {
+    let mut text = String::new();
+    for (path, changelog) in changelogs.iter() {
+        text += &format!(
+            "## `{}`\n\n`{}` {}..{} ({} commits)\n\n",
+            path.display(),
+            changelog.repo_url,
+            &changelog.old_commit[..12.min(changelog.old_commit.len())],
+            &changelog.new_commit[..12.min(changelog.new_commit.len())],
+            changelog.commits.len(),
+        );
+        for commit in changelog.commits.iter() {
+            text += &format!(
+                "- {} {} ({}, {})\n",
+                &commit.commit[..12.min(commit.commit.len())],
+                commit.subject,
+                commit.author,
+                format_date(commit.date),
+            );
+        }
+        text += "\n";
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TempDir, git as test_git, init_repo, file_url};
+
+    #[tokio::test]
+    async fn project_commit_log() {
+        let tmp = TempDir::new("changelog");
+        let repo = tmp.path().join("remote/build");
+        let old_commit = init_repo(&repo);
+        for subject in ["Add foo", "Bump version"] {
+            test_git(&repo, &["commit", "--quiet", "--allow-empty", "-m", subject]);
+        }
+        let new_commit = test_git(&repo, &["rev-parse", "HEAD"]);
+
+        let url = GitUrl::parse(&file_url(&repo)).unwrap();
+        let cache = tmp.path().join("cache");
+        std::fs::create_dir_all(&cache).unwrap();
+        let commits = commit_log(&cache, &url, &old_commit, &url, &new_commit).await.unwrap();
+        assert_eq!(commits.iter().map(|x| x.subject.as_str()).collect::<Vec<_>>(), ["Bump version", "Add foo"]);
+        assert_eq!(commits[0].commit, new_commit);
+        assert_eq!(commits[0].author, "Test");
+        assert_eq!(format_date(commits[0].date), "2023-11-14");
+
+        // The second run is served from the cache.
+        std::fs::remove_dir_all(&repo).unwrap();
+        assert_eq!(commit_log(&cache, &url, &old_commit, &url, &new_commit).await.unwrap(), commits);
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use repo_manifest::resolver::Manifest;
+use crate::lock::{
+    Lockset,
+    UpdateLocksetError,
+    EnsureStorePathError,
+};
+use crate::overrides::AppliedOverride;
+use crate::rules::FetchRules;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchOptions {
+    pub fetch_lfs: bool,
+    pub fetch_submodules: bool,
+}
+
+/// The fetch options detected from the contents of a locked commit, as recorded in a lockfile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetectedFetchOptions {
+    /// The commit whose store path was inspected.
+    pub commit: String,
+    #[serde(flatten)]
+    pub options: FetchOptions,
+}
+
+async fn uses_lfs(path: &Path) -> io::Result<bool> {
+    let mut dir = fs::read_dir(path).await?;
+    let mut subdirs = vec![];
+    while let Some(child) = dir.next_entry().await? {
+        let file_type = child.file_type().await?;
+        if file_type.is_dir() {
+            subdirs.push(child.path());
+        } else if child.file_name() == ".gitattributes" {
+            let text = fs::read(child.path()).await?;
+            if String::from_utf8_lossy(&text).lines().any(|x| x.contains("filter=lfs")) {
+                return Ok(true);
+            }
+        }
+    }
+
+    for subdir in subdirs {
+        if Box::pin(uses_lfs(&subdir)).await? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Whether `rules` or an applied override explicitly set the `fetch_lfs` option of a project.
+fn sets_fetch_lfs(rules: &FetchRules, overrides: &BTreeMap<PathBuf, AppliedOverride>, path: &Path) -> bool {
+    rules.sets_fetch_lfs(path)
+        || overrides.get(path).is_some_and(|x| x.project_override.fetch_lfs.is_some())
+}
+
+/// Whether `rules` or an applied override explicitly set the `fetch_submodules` option of a
+/// project.
+fn sets_fetch_submodules(rules: &FetchRules, overrides: &BTreeMap<PathBuf, AppliedOverride>, path: &Path) -> bool {
+    rules.sets_fetch_submodules(path)
+        || overrides.get(path).is_some_and(|x| x.project_override.fetch_submodules.is_some())
+}
+
+/// Detects whether a checked-out project tree needs Git LFS (some `.gitattributes` file uses
+/// `filter=lfs`) or submodules (there is a `.gitmodules` file).
+pub async fn detect_fetch_options(store_path: &Path) -> io::Result<FetchOptions> {
+    Ok(FetchOptions {
+        fetch_lfs: uses_lfs(store_path).await?,
+        fetch_submodules: fs::try_exists(store_path.join(".gitmodules")).await?,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum DetectFetchOptionsError {
+    #[error("error ensuring that the Nix store path exists")]
+    EnsureStorePath(#[from] EnsureStorePathError),
+    #[error("error inspecting store path of `{0}`")]
+    IO(PathBuf, #[source] io::Error),
+    #[error("error re-locking `{0}` with the detected fetch options")]
+    Relock(PathBuf, #[source] UpdateLocksetError),
+}
+
+/// Inspects the locked store paths of all active projects and flips their `fetch_lfs` and
+/// `fetch_submodules` options according to their contents, unless the option is explicitly set
+/// by `rules` or `overrides`. Projects are only re-locked if the change affects their contents.
+/// The detected options are recorded in the entries, and projects whose locked commit was already
+/// inspected aren't inspected again. Returns the old and new options of all changed projects.
+pub async fn update_detected_fetch_options(lockset: &mut Lockset, rules: &FetchRules, overrides: &BTreeMap<PathBuf, AppliedOverride>) -> Result<BTreeMap<PathBuf, (FetchOptions, FetchOptions)>, DetectFetchOptionsError> {
+    let paths: Vec<_> = lockset
+        .entries
+        .iter()
+        .filter(|(_, entry)| entry.project.active && entry.lock.is_some())
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    let mut changes = BTreeMap::new();
+    for path in paths {
+        let entry = &lockset.entries[&path];
+        let lock = entry.lock.as_ref().unwrap();
+        let detected = match &entry.detected_fetch_options {
+            Some(detected) if detected.commit == lock.commit => detected.options,
+            _ => {
+                lockset.ensure_store_path(&path).await?;
+                detect_fetch_options(&lock.path)
+                    .await
+                    .map_err(|e| DetectFetchOptionsError::IO(path.clone(), e))?
+            },
+        };
+
+        let entry = lockset.entries.get_mut(&path).unwrap();
+        entry.detected_fetch_options = Some(DetectedFetchOptions {
+            commit: entry.lock.as_ref().unwrap().commit.clone(),
+            options: detected,
+        });
+
+        let repo_ref = &mut entry.project.repo_ref;
+        let old = FetchOptions {
+            fetch_lfs: repo_ref.fetch_lfs,
+            fetch_submodules: repo_ref.fetch_submodules,
+        };
+        let new = FetchOptions {
+            fetch_lfs: if sets_fetch_lfs(rules, overrides, &path) { old.fetch_lfs } else { detected.fetch_lfs },
+            fetch_submodules: if sets_fetch_submodules(rules, overrides, &path) { old.fetch_submodules } else { detected.fetch_submodules },
+        };
+        if old == new {
+            continue;
+        }
+
+        repo_ref.fetch_lfs = new.fetch_lfs;
+        repo_ref.fetch_submodules = new.fetch_submodules;
+
+        // Disabling LFS for a project without any LFS files doesn't change its contents, so the
+        // existing lock stays valid. Everything else needs a fresh prefetch.
+        let lfs_only_disabled = old.fetch_submodules == new.fetch_submodules && !new.fetch_lfs;
+        if !lfs_only_disabled {
+            eprintln!("Re-locking `{}` with the detected fetch options...", path.display());
+            entry.lock = None;
+            lockset.update(&path)
+                .await
+                .map_err(|e| DetectFetchOptionsError::Relock(path.clone(), e))?;
+        }
+
+        changes.insert(path, (old, new));
+    }
+
+    Ok(changes)
+}
+
+/// Carries the detected fetch options of already locked projects over to the freshly resolved
+/// manifest projects, so that they stick across runs (and don't invalidate the locks), even ones
+/// without detection. Options explicitly set by `rules` or `overrides` take precedence.
+pub fn carry_over_fetch_options(lockset: &Lockset, manifest: &mut Manifest, rules: &FetchRules, overrides: &BTreeMap<PathBuf, AppliedOverride>) {
+    for (path, project) in manifest.projects.iter_mut() {
+        let Some(entry) = lockset.entries.get(path) else {
+            continue;
+        };
+        let Some(detected) = &entry.detected_fetch_options else {
+            continue;
+        };
+        let old_ref = &entry.project.repo_ref;
+        if old_ref.repo_url != project.repo_ref.repo_url || old_ref.revision != project.repo_ref.revision {
+            continue;
+        }
+
+        if !sets_fetch_lfs(rules, overrides, path) {
+            project.repo_ref.fetch_lfs = detected.options.fetch_lfs;
+        }
+        if !sets_fetch_submodules(rules, overrides, path) {
+            project.repo_ref.fetch_submodules = detected.options.fetch_submodules;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use repo_manifest::resolver::GitRepoRef;
+    use crate::lock::Lock;
+    use crate::overrides::ProjectOverride;
+    use crate::test_utils::{test_project, TempDir};
+
+    #[tokio::test]
+    async fn detect_lfs_and_submodules() {
+        let tmp = TempDir::new("detect");
+        let plain = tmp.path().join("plain");
+        std::fs::create_dir_all(&plain).unwrap();
+        std::fs::write(plain.join(".gitattributes"), "*.sh text eol=lf\n").unwrap();
+        assert_eq!(detect_fetch_options(&plain).await.unwrap(), FetchOptions {
+            fetch_lfs: false,
+            fetch_submodules: false,
+        });
+
+        let nested = tmp.path().join("nested");
+        std::fs::create_dir_all(nested.join("prebuilts/images")).unwrap();
+        std::fs::write(nested.join("prebuilts/images/.gitattributes"), "*.img filter=lfs diff=lfs merge=lfs -text\n").unwrap();
+        std::fs::write(nested.join(".gitmodules"), "[submodule \"foo\"]\n").unwrap();
+        assert_eq!(detect_fetch_options(&nested).await.unwrap(), FetchOptions {
+            fetch_lfs: true,
+            fetch_submodules: true,
+        });
+    }
+
+    #[tokio::test]
+    async fn overrides_take_precedence_over_detection() {
+        let tmp = TempDir::new("detect-overrides");
+        let store_path = tmp.path().join("store");
+        std::fs::create_dir_all(&store_path).unwrap();
+
+        let url = "https://android.googlesource.com/platform/external/foo";
+        let projects = HashMap::from(["external/foo", "external/bar"].map(|path| {
+            let mut project = test_project(path, url, "refs/heads/main");
+            project.repo_ref.fetch_lfs = true;
+            (PathBuf::from(path), project)
+        }));
+        let mut lockset = Lockset::new(&projects, &tmp.path().join("test.lock"));
+        for entry in lockset.entries.values_mut() {
+            entry.lock = Some(Lock {
+                commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+                nix_hash: String::new(),
+                path: store_path.clone(),
+                date: 0,
+            });
+        }
+        let overrides = BTreeMap::from([(PathBuf::from("external/foo"), AppliedOverride {
+            project_override: ProjectOverride { fetch_lfs: Some(true), ..Default::default() },
+            upstream: projects[Path::new("external/foo")].repo_ref.clone(),
+        })]);
+
+        // Neither project uses LFS, but the override keeps it enabled for `external/foo`.
+        let changes = update_detected_fetch_options(&mut lockset, &FetchRules::default(), &overrides).await.unwrap();
+        assert_eq!(changes.keys().collect::<Vec<_>>(), [Path::new("external/bar")]);
+        assert!(lockset.entries[Path::new("external/foo")].project.repo_ref.fetch_lfs);
+        assert!(!lockset.entries[Path::new("external/bar")].project.repo_ref.fetch_lfs);
+
+        // The detected option isn't carried over onto the overridden project either.
+        lockset.entries.get_mut(Path::new("external/foo")).unwrap().project.repo_ref.fetch_lfs = false;
+        let mut manifest = Manifest {
+            base_url: url.to_string(),
+            remotes: HashMap::new(),
+            default_remote: None,
+            projects: projects.clone(),
+        };
+        carry_over_fetch_options(&lockset, &mut manifest, &FetchRules::default(), &overrides);
+        assert!(manifest.projects[Path::new("external/foo")].repo_ref.fetch_lfs);
+        assert!(!manifest.projects[Path::new("external/bar")].repo_ref.fetch_lfs);
+    }
+
+    #[tokio::test]
+    async fn detected_options_are_recorded() {
+        let tmp = TempDir::new("detect-recorded");
+        let store_path = tmp.path().join("store");
+        std::fs::create_dir_all(&store_path).unwrap();
+        std::fs::write(store_path.join(".gitmodules"), "[submodule \"foo\"]\n").unwrap();
+
+        // Locked to a commit, so that re-locking doesn't need the remote.
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let url = "https://android.googlesource.com/platform/external/foo";
+        let project = test_project("external/foo", url, commit);
+        let projects = HashMap::from([(PathBuf::from("external/foo"), project.clone())]);
+        let mut lockset = Lockset::new(&projects, &tmp.path().join("test.lock"));
+        let lock = Lock {
+            commit: commit.to_string(),
+            nix_hash: String::new(),
+            path: store_path.clone(),
+            date: 0,
+        };
+        let entry = lockset.entries.get_mut(Path::new("external/foo")).unwrap();
+        entry.project.repo_ref.fetch_lfs = false;
+        entry.lock = Some(lock.clone());
+
+        let options = FetchOptions { fetch_lfs: false, fetch_submodules: true };
+        lockset.cache.insert(&GitRepoRef { fetch_submodules: true, fetch_lfs: false, ..project.repo_ref.clone() }, &lock);
+        let changes = update_detected_fetch_options(&mut lockset, &FetchRules::default(), &BTreeMap::new()).await.unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(lockset.entries[Path::new("external/foo")].detected_fetch_options, Some(DetectedFetchOptions {
+            commit: lock.commit.clone(),
+            options,
+        }));
+
+        // The same commit isn't inspected again, so its store path isn't needed anymore.
+        std::fs::remove_dir_all(&store_path).unwrap();
+        let changes = update_detected_fetch_options(&mut lockset, &FetchRules::default(), &BTreeMap::new()).await.unwrap();
+        assert!(changes.is_empty());
+
+        // The detected options stick even without detection.
+        let mut manifest = Manifest {
+            base_url: url.to_string(),
+            remotes: HashMap::new(),
+            default_remote: None,
+            projects: projects.clone(),
+        };
+        carry_over_fetch_options(&lockset, &mut manifest, &FetchRules::default(), &BTreeMap::new());
+        let repo_ref = &manifest.projects[Path::new("external/foo")].repo_ref;
+        assert_eq!(FetchOptions { fetch_lfs: repo_ref.fetch_lfs, fetch_submodules: repo_ref.fetch_submodules }, options);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::PathBuf;
+use serde::Serialize;
+use repo_manifest::git_url::GitUrl;
+use repo_manifest::resolver::Category;
+use crate::lock::LocksetEntry;
+use crate::utils::format_category;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+
+impl<T: PartialEq + Clone> Change<T> {
+    fn between(old: &T, new: &T) -> Option<Self> {
+        (old != new).then(|| Change { old: old.clone(), new: new.clone() })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProjectChanges {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<Change<Option<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo_url: Option<Change<GitUrl>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<Change<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch_lfs: Option<Change<bool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch_submodules: Option<Change<bool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub categories: Option<Change<BTreeSet<Category>>>,
+}
+
+impl ProjectChanges {
+    pub fn between(old: &LocksetEntry, new: &LocksetEntry) -> Self {
+        let (old_ref, new_ref) = (&old.project.repo_ref, &new.project.repo_ref);
+        ProjectChanges {
+            commit: Change::between(
+                &old.lock.as_ref().map(|x| x.commit.clone()),
+                &new.lock.as_ref().map(|x| x.commit.clone()),
+            ),
+            repo_url: Change::between(&old_ref.repo_url, &new_ref.repo_url),
+            revision: Change::between(&old_ref.revision, &new_ref.revision),
+            fetch_lfs: Change::between(&old_ref.fetch_lfs, &new_ref.fetch_lfs),
+            fetch_submodules: Change::between(&old_ref.fetch_submodules, &new_ref.fetch_submodules),
+            categories: Change::between(&old.project.categories, &new.project.categories),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == ProjectChanges::default()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LockfileDiff {
+    pub added: BTreeSet<PathBuf>,
+    pub removed: BTreeSet<PathBuf>,
+    /// Projects that were moved to a different path, by old path.
+    pub moved: BTreeMap<PathBuf, PathBuf>,
+    /// Changes of all projects present in both lockfiles, by new path.
+    pub changed: BTreeMap<PathBuf, ProjectChanges>,
+}
+
+/// Compares the active projects of two lockfiles. A removed and an added project with the same
+/// repo URL are reported as a moved project.
+pub fn diff_lockfiles(old: &BTreeMap<PathBuf, LocksetEntry>, new: &BTreeMap<PathBuf, LocksetEntry>) -> LockfileDiff {
+    let old: BTreeMap<_, _> = old.iter().filter(|(_, x)| x.project.active).collect();
+    let new: BTreeMap<_, _> = new.iter().filter(|(_, x)| x.project.active).collect();
+
+    let mut diff = LockfileDiff::default();
+    let mut removed: Vec<_> = old.keys().filter(|x| !new.contains_key(*x)).copied().collect();
+    for (path, entry) in new.iter() {
+        let old_entry = match old.get(path) {
+            Some(old_entry) => old_entry,
+            None => {
+                let moved_from = removed
+                    .iter()
+                    .position(|x| old[x].project.repo_ref.repo_url == entry.project.repo_ref.repo_url);
+                match moved_from {
+                    Some(i) => {
+                        let old_path = removed.remove(i);
+                        diff.moved.insert(old_path.clone(), (*path).clone());
+                        old[old_path]
+                    },
+                    None => {
+                        diff.added.insert((*path).clone());
+                        continue;
+                    },
+                }
+            },
+        };
+
+        let changes = ProjectChanges::between(old_entry, entry);
+        if !changes.is_empty() {
+            diff.changed.insert((*path).clone(), changes);
+        }
+    }
+    diff.removed = removed.into_iter().cloned().collect();
+
+    diff
+}
+
+fn format_commit(commit: &Option<String>) -> &str {
+    match commit {
+        Some(commit) => &commit[..commit.len().min(12)],
+        None => "(unlocked)",
+    }
+}
+
+fn format_categories(categories: &BTreeSet<Category>) -> String {
+    let names: Vec<_> = categories.iter().map(format_category).collect();
+    names.join(", ")
+}
+
+impl fmt::Display for LockfileDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty() && self.changed.is_empty() {
+            return writeln!(f, "No changes.");
+        }
+
+        if !self.added.is_empty() {
+            writeln!(f, "Added projects:")?;
+            for path in self.added.iter() {
+                writeln!(f, "  + {}", path.display())?;
+            }
+        }
+        if !self.removed.is_empty() {
+            writeln!(f, "Removed projects:")?;
+            for path in self.removed.iter() {
+                writeln!(f, "  - {}", path.display())?;
+            }
+        }
+        if !self.moved.is_empty() {
+            writeln!(f, "Moved projects:")?;
+            for (old, new) in self.moved.iter() {
+                writeln!(f, "  {} -> {}", old.display(), new.display())?;
+            }
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "Changed projects:")?;
+            for (path, changes) in self.changed.iter() {
+                writeln!(f, "  {}:", path.display())?;
+                if let Some(Change { old, new }) = &changes.commit {
+                    writeln!(f, "    commit: {} -> {}", format_commit(old), format_commit(new))?;
+                }
+                if let Some(Change { old, new }) = &changes.repo_url {
+                    writeln!(f, "    url: {old} -> {new}")?;
+                }
+                if let Some(Change { old, new }) = &changes.revision {
+                    writeln!(f, "    revision: {old} -> {new}")?;
+                }
+                if let Some(Change { old, new }) = &changes.fetch_lfs {
+                    writeln!(f, "    fetch_lfs: {old} -> {new}")?;
+                }
+                if let Some(Change { old, new }) = &changes.fetch_submodules {
+                    writeln!(f, "    fetch_submodules: {old} -> {new}")?;
+                }
+                if let Some(Change { old, new }) = &changes.categories {
+                    writeln!(f, "    categories: [{}] -> [{}]", format_categories(old), format_categories(new))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::lock::Lock;
+    use crate::test_utils::test_project;
+
+    fn entry(path: &str, url: &str, commit: &str) -> (PathBuf, LocksetEntry) {
+        (PathBuf::from(path), LocksetEntry {
+            project: test_project(path, url, "refs/heads/main"),
+            lock: Some(Lock {
+                commit: commit.repeat(40),
+                nix_hash: String::new(),
+                path: PathBuf::new(),
+                date: 0,
+            }),
+            applied_override: None,
+            signature: None,
+            detected_fetch_options: None,
+        })
+    }
+
+    #[test]
+    fn diff_projects() {
+        let old = BTreeMap::from([
+            entry("build/make", "https://example.com/platform/build", "a"),
+            entry("art", "https://example.com/platform/art", "a"),
+            entry("removed", "https://example.com/platform/removed", "a"),
+            entry("old/location", "https://example.com/platform/moved", "a"),
+        ]);
+        let mut new = BTreeMap::from([
+            entry("build/make", "https://example.com/platform/build", "b"),
+            entry("art", "https://example.com/platform/art", "a"),
+            entry("new/location", "https://example.com/platform/moved", "a"),
+            entry("added", "https://example.com/platform/added", "a"),
+        ]);
+        let art = new.get_mut(Path::new("art")).unwrap();
+        art.project.repo_ref.fetch_lfs = false;
+        art.project.categories.insert(Category::DeviceSpecific("oriole".to_string()));
+
+        let diff = diff_lockfiles(&old, &new);
+        assert_eq!(diff.added, BTreeSet::from([PathBuf::from("added")]));
+        assert_eq!(diff.removed, BTreeSet::from([PathBuf::from("removed")]));
+        assert_eq!(diff.moved, BTreeMap::from([(PathBuf::from("old/location"), PathBuf::from("new/location"))]));
+        assert_eq!(diff.changed.keys().collect::<Vec<_>>(), [Path::new("art"), Path::new("build/make")]);
+        assert_eq!(diff.changed[Path::new("build/make")].commit, Some(Change {
+            old: Some("a".repeat(40)),
+            new: Some("b".repeat(40)),
+        }));
+        let art_changes = &diff.changed[Path::new("art")];
+        assert!(art_changes.commit.is_none());
+        assert_eq!(art_changes.fetch_lfs, Some(Change { old: true, new: false }));
+        assert!(art_changes.categories.is_some());
+
+        assert_eq!(diff_lockfiles(&old, &old).to_string(), "No changes.\n");
+    }
+}
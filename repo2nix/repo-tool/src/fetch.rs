@@ -1,11 +1,53 @@
+use std::env;
 use std::io;
 use std::path::PathBuf;
-use url::Url;
+use std::sync::LazyLock;
+use repo_manifest::git_url::GitUrl;
 use thiserror::Error;
 use serde::Deserialize;
 use serde_json;
 use tokio::process::Command;
 
+/// Maps URL prefixes to mirror URL prefixes, like git's `url.<base>.insteadOf`. The mirrors are
+/// only used to fetch from, while the lockfiles keep the canonical URLs.
+#[derive(Debug, Default)]
+pub struct MirrorMap {
+    mirrors: Vec<(String, String)>,
+}
+
+impl MirrorMap {
+    /// Parses the `ROBOTNIX_GIT_MIRRORS` format shared with robotnix's Python scripts, i.e.
+    /// `<url prefix>=<mirror prefix>` pairs separated by `|`.
+    pub fn parse(text: &str) -> Self {
+        let mut mirrors = vec![];
+        for entry in text.split('|').filter(|x| !x.is_empty()) {
+            match entry.split_once('=') {
+                Some((prefix, mirror)) => mirrors.push((prefix.to_string(), mirror.to_string())),
+                None => eprintln!("Ignoring invalid git mirror specification `{entry}`"),
+            }
+        }
+        MirrorMap { mirrors }
+    }
+
+    /// Rewrites `url` using the longest matching URL prefix, if any.
+    pub fn apply(&self, url: &str) -> String {
+        self.mirrors
+            .iter()
+            .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, mirror)| format!("{mirror}{}", &url[prefix.len()..]))
+            .unwrap_or(url.to_string())
+    }
+}
+
+static MIRRORS: LazyLock<MirrorMap> = LazyLock::new(|| {
+    MirrorMap::parse(&env::var("ROBOTNIX_GIT_MIRRORS").unwrap_or_default())
+});
+
+pub fn mirrored_url(url: &str) -> String {
+    MIRRORS.apply(url)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct NixPrefetchGitOutput {
     #[allow(dead_code)]
@@ -49,7 +91,24 @@ pub enum NixPrefetchGitError {
     Parse(#[from] serde_json::Error),
 }
 
-pub async fn nix_prefetch_git(repo_url: &Url, revision: &str, fetch_lfs: bool, fetch_submodules: bool) -> Result<NixPrefetchGitOutput, NixPrefetchGitError> {
+/// Replicates the store path name `nix-prefetch-git` chooses for a given URL and revision.
+pub fn nix_prefetch_git_name(repo_url: &GitUrl, revision: &str) -> String {
+    let basename = repo_url
+        .path()
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    let basename = basename.strip_suffix(".git").unwrap_or(basename);
+
+    if revision.bytes().all(|x| x.is_ascii_lowercase() || x.is_ascii_digit()) {
+        format!("{basename}-{}", &revision[..revision.len().min(7)])
+    } else {
+        basename.to_string()
+    }
+}
+
+pub async fn nix_prefetch_git(repo_url: &GitUrl, revision: &str, fetch_lfs: bool, fetch_submodules: bool) -> Result<NixPrefetchGitOutput, NixPrefetchGitError> {
     eprintln!("Prefetching `{}`, revision {}...", repo_url, revision);
     let mut flag_args = vec![];
     if fetch_lfs {
@@ -60,7 +119,7 @@ pub async fn nix_prefetch_git(repo_url: &Url, revision: &str, fetch_lfs: bool, f
     }
     let output = Command::new("nix-prefetch-git")
         .arg("--url")
-        .arg(repo_url.as_str())
+        .arg(mirrored_url(&repo_url.to_string()))
         .arg("--rev")
         .arg(&revision)
         .args(&flag_args)
@@ -95,7 +154,7 @@ pub enum GitLsRemoteError {
 pub async fn git_ls_remote(url: &str, git_ref: &str) -> Result<String, GitLsRemoteError> {
     let output = Command::new("git")
         .arg("ls-remote")
-        .arg(url)
+        .arg(mirrored_url(url))
         .output()
         .await?;
 
@@ -107,13 +166,47 @@ pub async fn git_ls_remote(url: &str, git_ref: &str) -> Result<String, GitLsRemo
     }
 
     let output_str = std::str::from_utf8(&output.stdout).map_err(GitLsRemoteError::Utf8)?;
+    let peeled_ref = format!("{git_ref}^{{}}");
+    let mut commit = None;
     for line in output_str.split("\n") {
-        if line.ends_with(git_ref) {
-            let commit = line.split("\t").next().unwrap();
-            return Ok(commit.to_string());
+        // Annotated tags are listed twice, once pointing to the tag object and once (with a `^{}`
+        // suffix) pointing to the tagged commit. We're only interested in the latter.
+        if line.ends_with(&peeled_ref) {
+            return Ok(line.split("\t").next().unwrap().to_string());
+        }
+        if commit.is_none() && line.ends_with(git_ref) {
+            commit = Some(line.split("\t").next().unwrap().to_string());
         }
     }
 
+    if let Some(commit) = commit {
+        return Ok(commit);
+    }
+
     Err(GitLsRemoteError::RevNotFound)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirror_longest_prefix() {
+        let mirrors = MirrorMap::parse(
+            "https://android.googlesource.com=https://gerrit.internal/aosp|https://android.googlesource.com/platform/art=/srv/mirror/art|invalid"
+        );
+        assert_eq!(
+            mirrors.apply("https://android.googlesource.com/platform/build"),
+            "https://gerrit.internal/aosp/platform/build"
+        );
+        assert_eq!(
+            mirrors.apply("https://android.googlesource.com/platform/art"),
+            "/srv/mirror/art"
+        );
+        assert_eq!(
+            mirrors.apply("https://github.com/LineageOS/android"),
+            "https://github.com/LineageOS/android"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LockLockfileError {
+    #[error("error locking `{0}`")]
+    IO(PathBuf, #[source] io::Error),
+    #[error("lockfile `{0}` is in use by another repo-tool process ({1})")]
+    Busy(PathBuf, String),
+}
+
+/// Tries to take an exclusive `flock(2)` lock on `file` without blocking. Returns `false` if
+/// another process holds it. (`File::try_lock` would need Rust 1.89.)
+fn try_flock(file: &File) -> io::Result<bool> {
+    // SAFETY: `file` keeps the file descriptor open for the duration of the call.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    if error.kind() == io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(error)
+    }
+}
+
+/// An exclusive advisory lock on a lockfile, held until dropped. As the lockfile itself is
+/// replaced on every write, the lock is taken on a separate `<lockfile>.lck` file, which also
+/// records the PID and command line of the holding process.
+#[derive(Debug)]
+pub struct LockfileLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl LockfileLock {
+    pub fn acquire(lockfile_path: &Path) -> Result<Self, LockLockfileError> {
+        let mut path = lockfile_path.as_os_str().to_owned();
+        path.push(".lck");
+        let path = PathBuf::from(path);
+        let io_error = |e| LockLockfileError::IO(path.clone(), e);
+
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .map_err(io_error)?;
+
+            match try_flock(&file) {
+                Ok(true) => (),
+                Ok(false) => {
+                    let holder = fs::read_to_string(&path).unwrap_or_default();
+                    let holder = match holder.trim() {
+                        "" => "unknown process".to_string(),
+                        holder => holder.to_string(),
+                    };
+                    return Err(LockLockfileError::Busy(lockfile_path.to_path_buf(), holder));
+                },
+                Err(e) => return Err(io_error(e)),
+            }
+
+            // The previous holder may have removed the file between us opening and locking it,
+            // in which case we hold a lock on a file nobody else will see.
+            let locked_ino = file.metadata().map_err(io_error)?.ino();
+            match fs::metadata(&path) {
+                Ok(metadata) if metadata.ino() == locked_ino => (),
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(io_error(e)),
+            }
+
+            let args: Vec<_> = env::args().collect();
+            file.set_len(0).map_err(io_error)?;
+            writeln!(file, "PID {}: {}", process::id(), args.join(" ")).map_err(io_error)?;
+
+            return Ok(LockfileLock { path, file });
+        }
+    }
+}
+
+impl Drop for LockfileLock {
+    fn drop(&mut self) {
+        // Remove the file while still holding the lock, see `acquire`.
+        let _ = fs::remove_file(&self.path);
+        // SAFETY: `self.file` keeps the file descriptor open.
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn exclusive_lock() {
+        let tmp = TempDir::new("file-lock");
+        let lockfile_path = tmp.path().join("test.lock");
+
+        let lock = LockfileLock::acquire(&lockfile_path).unwrap();
+        match LockfileLock::acquire(&lockfile_path) {
+            Err(LockLockfileError::Busy(path, holder)) => {
+                assert_eq!(path, lockfile_path);
+                assert!(holder.starts_with(&format!("PID {}:", process::id())));
+            },
+            other => panic!("expected lock to be busy, got {other:?}"),
+        }
+
+        drop(lock);
+        assert!(!tmp.path().join("test.lock.lck").exists());
+        LockfileLock::acquire(&lockfile_path).unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::process::Command;
+use thiserror::Error;
+use repo_manifest::git_url::GitUrl;
+use crate::fetch::mirrored_url;
+
+#[derive(Debug, Error)]
+pub enum GitError {
+    #[error("couldn't spawn `git` process")]
+    ProcessSpawn(#[from] io::Error),
+    #[error("`git {0}` did not return successfully ({1:?}), stderr:\n{2}")]
+    NonzeroExitStatus(String, Option<i32>, String),
+    #[error("couldn't parse `git` output")]
+    Parse,
+}
+
+/// The default directory for the bare repos used to look up commits, i.e.
+/// `$XDG_CACHE_HOME/repo-tool/git`.
+pub fn default_git_cache_dir() -> PathBuf {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|x| Path::new(&x).join(".cache")))
+        .unwrap_or(env::temp_dir());
+    cache_home.join("repo-tool/git")
+}
+
+pub async fn git(git_dir: &Path, args: &[&str]) -> Result<Vec<u8>, GitError> {
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(git_dir)
+        .args(args)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(GitError::NonzeroExitStatus(
+            args.join(" "),
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string()
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+/// Returns the bare repo in `git_cache_dir` used for `repo_url`, creating it if necessary.
+pub async fn bare_repo(git_cache_dir: &Path, repo_url: &GitUrl) -> Result<PathBuf, GitError> {
+    let name: String = repo_url
+        .to_string()
+        .chars()
+        .map(|x| if x.is_ascii_alphanumeric() || x == '-' || x == '.' { x } else { '_' })
+        .collect();
+    let git_dir = git_cache_dir.join(format!("{name}.git"));
+    if !fs::try_exists(&git_dir).await? {
+        git(&git_dir, &["init", "--quiet", "--bare"]).await?;
+    }
+    Ok(git_dir)
+}
+
+pub async fn has_commit(git_dir: &Path, commit: &str) -> bool {
+    git(git_dir, &["cat-file", "-e", &format!("{commit}^{{commit}}")]).await.is_ok()
+}
+
+/// Makes sure that `commit` and its history are present in the bare repo at `git_dir`. Only
+/// commits (and no trees or blobs) are fetched, if the server supports it. Trees and blobs are
+/// fetched lazily from the same URL when they are needed later on.
+pub async fn ensure_commit(git_dir: &Path, repo_url: &GitUrl, commit: &str) -> Result<(), GitError> {
+    if !has_commit(git_dir, commit).await {
+        let url = mirrored_url(&repo_url.to_string());
+        git(git_dir, &["fetch", "--quiet", "--filter=tree:0", &url, commit]).await?;
+    }
+    Ok(())
+}
+
+/// Fetches the history of `git_ref` into the bare repo at `git_dir`, and returns the commit it
+/// points to.
+pub async fn fetch_ref(git_dir: &Path, repo_url: &GitUrl, git_ref: &str) -> Result<String, GitError> {
+    let url = mirrored_url(&repo_url.to_string());
+    git(git_dir, &["fetch", "--quiet", "--filter=tree:0", &url, git_ref]).await?;
+    let output = git(git_dir, &["rev-parse", "FETCH_HEAD^{commit}"]).await?;
+    let commit = std::str::from_utf8(&output).map_err(|_| GitError::Parse)?;
+    Ok(commit.trim().to_string())
+}
+
+/// Whether `commit` is an ancestor of (or the same as) `descendant`. Missing commits are
+/// reported as not being ancestors.
+pub async fn is_ancestor(git_dir: &Path, commit: &str, descendant: &str) -> bool {
+    git(git_dir, &["merge-base", "--is-ancestor", commit, descendant]).await.is_ok()
+}
+
+/// The committer date of `commit` as UNIX timestamp.
+pub async fn commit_date(git_dir: &Path, commit: &str) -> Result<u64, GitError> {
+    let output = git(git_dir, &["log", "-1", "--format=%ct", commit]).await?;
+    let date = std::str::from_utf8(&output).map_err(|_| GitError::Parse)?;
+    date.trim().parse().map_err(|_| GitError::Parse)
+}
@@ -0,0 +1,147 @@
+use std::path::PathBuf;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+const NIX_BASE32_ALPHABET: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+const NIX_STORE_DIR: &str = "/nix/store";
+
+#[derive(Debug, Error)]
+pub enum ParseHashError {
+    #[error("`{0}` is not a valid SHA-256 hash in SRI, Nix base32 or hex format")]
+    InvalidSha256(String),
+}
+
+pub fn nix_base32_encode(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return String::new();
+    }
+
+    let len = (bytes.len() * 8 - 1) / 5 + 1;
+    let mut out = String::with_capacity(len);
+    for n in (0..len).rev() {
+        let b = n * 5;
+        let i = b / 8;
+        let j = b % 8;
+        let mut c = bytes[i] >> j;
+        if i + 1 < bytes.len() && j > 3 {
+            c |= bytes[i + 1] << (8 - j);
+        }
+        out.push(NIX_BASE32_ALPHABET[(c & 0x1f) as usize] as char);
+    }
+
+    out
+}
+
+pub fn nix_base32_decode(text: &str) -> Option<Vec<u8>> {
+    let len = text.len() * 5 / 8;
+    let mut out = vec![0u8; len];
+    for (n, c) in text.bytes().rev().enumerate() {
+        let digit = NIX_BASE32_ALPHABET.iter().position(|x| *x == c)? as u8;
+        let b = n * 5;
+        let i = b / 8;
+        let j = b % 8;
+        if i >= len {
+            // Only padding bits may spill over the end of the output.
+            if digit != 0 {
+                return None;
+            }
+            continue;
+        }
+        out[i] |= digit << j;
+        let carry = ((digit as u16) << j) >> 8;
+        if i + 1 < len {
+            out[i + 1] |= carry as u8;
+        } else if carry != 0 {
+            return None;
+        }
+    }
+
+    Some(out)
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+/// Parses a SHA-256 hash given either as an SRI hash (`sha256-<base64>`), as a Nix base32 string
+/// (as used by `nix-prefetch-git`'s `sha256` field and the legacy `repo-*.json` files) or as a
+/// hex string.
+pub fn parse_sha256(hash: &str) -> Result<[u8; 32], ParseHashError> {
+    let bytes = if let Some(b64) = hash.strip_prefix("sha256-") {
+        BASE64.decode(b64).ok()
+    } else {
+        match hash.len() {
+            52 => nix_base32_decode(hash),
+            64 => decode_hex(hash),
+            _ => None,
+        }
+    };
+
+    bytes
+        .and_then(|x| x.try_into().ok())
+        .ok_or(ParseHashError::InvalidSha256(hash.to_string()))
+}
+
+pub fn sha256_to_sri(hash: &[u8; 32]) -> String {
+    format!("sha256-{}", BASE64.encode(hash))
+}
+
+/// Computes the store path of a fixed-output derivation with recursive (NAR) SHA-256 hashing,
+/// i.e. the path `nix-prefetch-git` (or `fetchgit`) ends up adding the source tree at.
+pub fn fixed_output_store_path(nar_hash: &[u8; 32], name: &str) -> PathBuf {
+    let fingerprint = format!("source:sha256:{}:{NIX_STORE_DIR}:{name}", encode_hex(nar_hash));
+    let digest = Sha256::digest(fingerprint.as_bytes());
+
+    let mut compressed = [0u8; 20];
+    for (i, x) in digest.iter().enumerate() {
+        compressed[i % 20] ^= x;
+    }
+
+    PathBuf::from(format!("{NIX_STORE_DIR}/{}-{name}", nix_base32_encode(&compressed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nix_base32_roundtrip() {
+        let sha256 = "0xqz7hj3rb3f6jfvlwrsg1r624q1d9xw2fx75s28i3wqxbzrqkyp";
+        let bytes = nix_base32_decode(sha256).unwrap();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(nix_base32_encode(&bytes), sha256);
+    }
+
+    #[test]
+    fn parse_hash_formats() {
+        let sri = "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A=";
+        let bytes = parse_sha256(sri).unwrap();
+        assert_eq!(sha256_to_sri(&bytes), sri);
+        assert_eq!(parse_sha256(&encode_hex(&bytes)).unwrap(), bytes);
+        assert_eq!(parse_sha256(&nix_base32_encode(&bytes)).unwrap(), bytes);
+        assert!(parse_sha256("sha256-AAAA").is_err());
+        assert!(parse_sha256("not a hash").is_err());
+    }
+
+    #[test]
+    fn store_path_matches_nix_prefetch_git() {
+        // Taken from a lockfile generated by `repo-tool fetch`.
+        let nar_hash = parse_sha256("sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A=").unwrap();
+        assert_eq!(
+            fixed_output_store_path(&nar_hash, "platform_art-1cc15f8"),
+            PathBuf::from("/nix/store/caphp23qx36iqbpcfa0wx1q16h8sq65x-platform_art-1cc15f8"),
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use serde::Deserialize;
+use thiserror::Error;
+use repo_manifest::git_url::{GitUrl, ParseGitUrlError};
+use repo_manifest::resolver::{
+    Project,
+    GitRepoRef,
+    Category,
+    LinkCopyFile,
+};
+use crate::cache::LockCache;
+use crate::fetch::nix_prefetch_git_name;
+use crate::hash::{
+    parse_sha256,
+    sha256_to_sri,
+    fixed_output_store_path,
+    ParseHashError,
+};
+use crate::lock::{
+    Lock,
+    LocksetEntry,
+    Lockset,
+};
+
+/// A project entry of the `repo-*.json` files generated by `scripts/mk_repo_file.py`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyProject {
+    pub url: String,
+    pub rev: Option<String>,
+    pub revision_expr: String,
+    pub date_time: Option<u64>,
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub fetch_submodules: bool,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub copyfiles: Vec<LinkCopyFile>,
+    #[serde(default)]
+    pub linkfiles: Vec<LinkCopyFile>,
+    // unsupported fields: tree (only used as a cache key by mk_repo_file.py)
+}
+
+#[derive(Debug, Error)]
+pub enum ReadLegacyRepoFileError {
+    #[error("error reading legacy repo file")]
+    IO(#[from] io::Error),
+    #[error("error parsing legacy repo file")]
+    Parse(#[from] serde_json::Error),
+    #[error("invalid URL for project `{0}`")]
+    Url(PathBuf, #[source] ParseGitUrlError),
+    #[error("invalid sha256 for project `{0}`")]
+    Hash(PathBuf, #[source] ParseHashError),
+}
+
+pub fn legacy_to_entry(path: &Path, legacy: LegacyProject, fetch_lfs: bool) -> Result<LocksetEntry, ReadLegacyRepoFileError> {
+    let repo_url = GitUrl::parse(&legacy.url)
+        .map_err(|e| ReadLegacyRepoFileError::Url(path.to_path_buf(), e))?;
+
+    // Entries that were never fully prefetched by mk_repo_file.py are imported unlocked, so that
+    // the next `repo-tool fetch` picks them up.
+    let lock = match (legacy.rev, legacy.sha256) {
+        (Some(commit), Some(sha256)) => {
+            let nar_hash = parse_sha256(&sha256)
+                .map_err(|e| ReadLegacyRepoFileError::Hash(path.to_path_buf(), e))?;
+            // mk_repo_file.py prefetched tags by name rather than by commit, which changes the
+            // name of the store path.
+            let prefetched_rev = if legacy.revision_expr.starts_with("refs/tags/") {
+                &legacy.revision_expr
+            } else {
+                &commit
+            };
+            Some(Lock {
+                path: fixed_output_store_path(&nar_hash, &nix_prefetch_git_name(&repo_url, prefetched_rev)),
+                nix_hash: sha256_to_sri(&nar_hash),
+                commit,
+                // Older legacy files don't record commit dates.
+                date: legacy.date_time.unwrap_or(0),
+            })
+        },
+        _ => None,
+    };
+
+    Ok(LocksetEntry {
+        project: Project {
+            path: path.to_path_buf(),
+            groups: legacy.groups,
+            linkfiles: legacy.linkfiles,
+            copyfiles: legacy.copyfiles,
+            repo_ref: GitRepoRef {
+                repo_url,
+                revision: legacy.revision_expr,
+                fetch_lfs,
+                fetch_submodules: legacy.fetch_submodules,
+            },
+            categories: BTreeSet::from([Category::Default]),
+            lineage_deps: None,
+            active: true,
+        },
+        lock,
+        applied_override: None,
+        signature: None,
+        detected_fetch_options: None,
+    })
+}
+
+pub async fn import_legacy_repo_file(legacy_path: &Path, lockfile_path: &Path, fetch_lfs: bool) -> Result<Lockset, ReadLegacyRepoFileError> {
+    let legacy_projects: BTreeMap<PathBuf, LegacyProject> = serde_json::from_slice(
+        &fs::read(legacy_path).await?
+    )?;
+
+    let mut entries = BTreeMap::new();
+    for (path, legacy) in legacy_projects {
+        let entry = legacy_to_entry(&path, legacy, fetch_lfs)?;
+        entries.insert(path, entry);
+    }
+
+    Ok(Lockset {
+        entries,
+        path: lockfile_path.to_path_buf(),
+        manifest: None,
+        extra_manifests: vec![],
+        cache: LockCache::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    const LEGACY_FILE: &str = r#"{
+        "art": {
+            "url": "https://android.googlesource.com/platform/art",
+            "rev": "1cc15f8ed2b1a6ee6d8e9e7e6f3a5a5e8e3f5b6c",
+            "revisionExpr": "refs/heads/main",
+            "dateTime": 1700000000,
+            "sha256": "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A=",
+            "groups": ["pdk"],
+            "copyfiles": [{ "src": "a", "dest": "b" }]
+        },
+        "build/make": {
+            "url": "https://android.googlesource.com/platform/build",
+            "rev": "0123456789abcdef0123456789abcdef01234567",
+            "revisionExpr": "refs/tags/android-14.0.0_r1",
+            "sha256": "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A=",
+            "fetchSubmodules": true
+        },
+        "external/foo": {
+            "url": "https://android.googlesource.com/platform/external/foo",
+            "revisionExpr": "refs/heads/main"
+        }
+    }"#;
+
+    fn legacy_project(json: &str) -> LegacyProject {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn store_path_name_uses_prefetched_revision() {
+        let entry = legacy_to_entry(Path::new("art"), legacy_project(r#"{
+            "url": "https://github.com/GrapheneOS/platform_art",
+            "rev": "1cc15f8ed2b1a6ee6d8e9e7e6f3a5a5e8e3f5b6c",
+            "revisionExpr": "refs/heads/main",
+            "sha256": "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A="
+        }"#), true).unwrap();
+        let lock = entry.lock.unwrap();
+        assert_eq!(lock.path, PathBuf::from("/nix/store/caphp23qx36iqbpcfa0wx1q16h8sq65x-platform_art-1cc15f8"));
+        assert_eq!(lock.nix_hash, "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A=");
+        assert_eq!(lock.date, 0);
+        assert_eq!(lock.prefetched_revision(&entry.project.repo_ref), lock.commit);
+        assert!(entry.project.repo_ref.fetch_lfs);
+
+        // Tags were prefetched by name, so the store path has no revision suffix.
+        let entry = legacy_to_entry(Path::new("art"), legacy_project(r#"{
+            "url": "https://android.googlesource.com/platform/art",
+            "rev": "1cc15f8ed2b1a6ee6d8e9e7e6f3a5a5e8e3f5b6c",
+            "revisionExpr": "refs/tags/android-14.0.0_r1",
+            "sha256": "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A="
+        }"#), false).unwrap();
+        let lock = entry.lock.unwrap();
+        assert_eq!(lock.path, fixed_output_store_path(&parse_sha256(&lock.nix_hash).unwrap(), "art"));
+        assert_ne!(lock.path, fixed_output_store_path(&parse_sha256(&lock.nix_hash).unwrap(), "art-1cc15f8"));
+        // `ensure-store-paths` needs to prefetch the tag as well to recreate the store path.
+        let revision = lock.prefetched_revision(&entry.project.repo_ref);
+        assert_eq!(revision, "refs/tags/android-14.0.0_r1");
+        assert_eq!(lock.path, fixed_output_store_path(
+            &parse_sha256(&lock.nix_hash).unwrap(),
+            &nix_prefetch_git_name(&entry.project.repo_ref.repo_url, revision),
+        ));
+
+        // Tags locked by `repo-tool fetch` were prefetched by commit.
+        let fetched_lock = Lock {
+            path: fixed_output_store_path(&parse_sha256(&lock.nix_hash).unwrap(), "art-1cc15f8"),
+            ..lock.clone()
+        };
+        assert_eq!(fetched_lock.prefetched_revision(&entry.project.repo_ref), lock.commit);
+        assert_eq!(entry.project.repo_ref.revision, "refs/tags/android-14.0.0_r1");
+        assert!(!entry.project.repo_ref.fetch_lfs);
+
+        assert!(matches!(
+            legacy_to_entry(Path::new("art"), legacy_project(r#"{
+                "url": "https://android.googlesource.com/platform/art",
+                "rev": "1cc15f8ed2b1a6ee6d8e9e7e6f3a5a5e8e3f5b6c",
+                "revisionExpr": "refs/heads/main",
+                "sha256": "not a hash"
+            }"#), true),
+            Err(ReadLegacyRepoFileError::Hash(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn import_repo_file() {
+        let tmp = TempDir::new("legacy");
+        let legacy_path = tmp.path().join("repo-main.json");
+        fs::write(&legacy_path, LEGACY_FILE).await.unwrap();
+
+        let lockset = import_legacy_repo_file(&legacy_path, &tmp.path().join("main.lock"), true).await.unwrap();
+        assert_eq!(lockset.path, tmp.path().join("main.lock"));
+        assert_eq!(lockset.entries.keys().collect::<Vec<_>>(), [Path::new("art"), Path::new("build/make"), Path::new("external/foo")]);
+
+        let art = &lockset.entries[Path::new("art")];
+        assert_eq!(art.project.groups, ["pdk"]);
+        assert_eq!(art.project.copyfiles.len(), 1);
+        assert_eq!(art.lock.as_ref().unwrap().date, 1700000000);
+
+        let build = &lockset.entries[Path::new("build/make")];
+        assert!(build.project.repo_ref.fetch_submodules);
+        assert_eq!(build.lock.as_ref().unwrap().commit, "0123456789abcdef0123456789abcdef01234567");
+        assert!(build.lock.as_ref().unwrap().path.to_string_lossy().ends_with("-build"));
+
+        // Entries that were never prefetched are imported unlocked.
+        assert!(lockset.entries[Path::new("external/foo")].lock.is_none());
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::{BTreeMap, HashMap};
 use std::io;
 use std::str;
 use serde::{Serialize, Deserialize};
-use url::Url;
+use repo_manifest::git_url::{GitUrl, ParseGitUrlError};
 use tokio::fs;
 use tokio::process::Command;
 use thiserror::Error;
@@ -15,6 +15,7 @@ use repo_manifest::resolver::{
 };
 use crate::fetch::{
     nix_prefetch_git,
+    mirrored_url,
     NixPrefetchGitError,
     GitLsRemoteError,
 };
@@ -40,7 +41,7 @@ pub enum FetchHudsonDevicesError {
 
 pub async fn fetch_hudson_devices() -> Result<HashMap<String, HudsonDeviceInfo>, FetchHudsonDevicesError>  {
     let hudson_fetch = nix_prefetch_git(
-        &Url::parse("https://github.com/LineageOS/hudson").unwrap(),
+        &GitUrl::parse("https://github.com/LineageOS/hudson").unwrap(),
         "refs/heads/main",
         false,
         false,
@@ -86,7 +87,7 @@ pub enum GetDeviceReposError {
 
 pub async fn get_device_repos() -> Result<Vec<(String, String)>, GetDeviceReposError> {
     let mirror_fetch = nix_prefetch_git(
-        &Url::parse("https://github.com/LineageOS/mirror").unwrap(),
+        &GitUrl::parse("https://github.com/LineageOS/mirror").unwrap(),
         "refs/heads/main",
         false,
         false,
@@ -122,7 +123,7 @@ pub async fn get_repo_branches(repo: &str) -> Result<Vec<String>, GitLsRemoteErr
     println!("`git ls-remote`-ing {repo}...");
     let output = Command::new("git")
         .arg("ls-remote")
-        .arg(&format!("https://github.com/{repo}"))
+        .arg(mirrored_url(&format!("https://github.com/{repo}")))
         .output()
         .await
         .map_err(GitLsRemoteError::ProcessSpawn)?;
@@ -171,7 +172,7 @@ pub enum GetDevicesError {
     #[error("multiple possible device repos found for device `{0}`")]
     DuplicateDeviceRepo(String),
     #[error("invalid device repo url")]
-    Url(#[from] url::ParseError),
+    Url(#[from] ParseGitUrlError),
 }
 
 pub fn hudson_to_device_repo_branch(branch: &str) -> String {
@@ -206,7 +207,7 @@ pub async fn get_devices(allowlist: &Option<Vec<String>>, blocklist: &Option<Vec
                     let mut branch_repos = BTreeMap::new();
                     for branch in branches {
                         branch_repos.insert(branch.clone(), GitRepoRef {
-                            repo_url: Url::parse(&format!("https://github.com/LineageOS/android_device_{vendor}_{name}")).map_err(GetDevicesError::Url)?,
+                            repo_url: GitUrl::parse(&format!("https://github.com/LineageOS/android_device_{vendor}_{name}")).map_err(GetDevicesError::Url)?,
                             revision: format!("refs/heads/{}", hudson_to_device_repo_branch(&branch)),
                             fetch_lfs: true,
                             fetch_submodules: false,
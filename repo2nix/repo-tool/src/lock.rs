@@ -1,20 +1,31 @@
 use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 use std::io;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
 use thiserror::Error;
 use serde::{Serialize, Deserialize};
+use repo_manifest::git_url::GitUrl;
 use repo_manifest::resolver::{
+    GitRepoRef,
     Project,
 };
+use crate::cache::LockCache;
+use crate::detect::DetectedFetchOptions;
+use crate::overrides::AppliedOverride;
+use crate::signatures::VerifiedSignature;
 use crate::fetch::{
     nix_prefetch_git,
+    nix_prefetch_git_name,
     git_ls_remote,
     NixPrefetchGitError,
+    NixPrefetchGitOutput,
     GitLsRemoteError,
 };
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Lock {
     // TODO proper [u8, 20] type for commit with (de)?serializer
     pub commit: String,
@@ -23,6 +34,51 @@ pub struct Lock {
     pub date: u64,
 }
 
+impl From<NixPrefetchGitOutput> for Lock {
+    fn from(output: NixPrefetchGitOutput) -> Self {
+        Lock {
+            commit: output.rev,
+            nix_hash: output.hash,
+            path: output.path,
+            date: output.date,
+        }
+    }
+}
+
+impl Lock {
+    /// The revision `path` was prefetched with. This is the commit, except for tags imported from
+    /// legacy repo files, which `mk_repo_file.py` prefetched by name and whose store paths are
+    /// named after the tag.
+    pub fn prefetched_revision<'a>(&'a self, repo_ref: &'a GitRepoRef) -> &'a str {
+        let name = self.path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.split_once('-'))
+            .map(|(_, name)| name);
+        if repo_ref.revision.starts_with("refs/tags/")
+            && name == Some(nix_prefetch_git_name(&repo_ref.repo_url, &repo_ref.revision).as_str()) {
+            &repo_ref.revision
+        } else {
+            &self.commit
+        }
+    }
+}
+
+/// A manifest repo (or an extra manifest merged into the main one) a lockfile was generated from.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ManifestLock {
+    pub url: GitUrl,
+    /// The ref requested on the command line, if any.
+    pub git_ref: Option<String>,
+    /// The manifest file read from the repo.
+    pub manifest_name: PathBuf,
+    /// `None` for local manifest repos that were read from the working tree.
+    pub lock: Option<Lock>,
+    /// The verified signature of the ref (or commit), if signatures were verified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<VerifiedSignature>,
+}
+
 pub fn is_commit_id(commit_id: &str) -> bool {
     commit_id.as_bytes().len() == 40 &&
         commit_id.as_bytes().iter().all(|x| x.is_ascii_hexdigit())
@@ -37,11 +93,11 @@ pub enum UpdateLockError {
     #[error("commit ids returned by `git ls-remote` and `nix-prefetch-git` for rev `{0}` do not match")]
     CommitMismatch(String),
 }
-pub async fn update_lock(project: &Project, lock: &Option<Lock>) -> Result<(Lock, bool), UpdateLockError> {
+pub async fn update_lock(project: &Project, lock: &Option<Lock>, cache: &LockCache) -> Result<(Lock, bool), UpdateLockError> {
     let current_commit = if is_commit_id(&project.repo_ref.revision) {
         project.repo_ref.revision.clone()
     } else {
-        git_ls_remote(project.repo_ref.repo_url.as_str(), &project.repo_ref.revision)
+        git_ls_remote(&project.repo_ref.repo_url.to_string(), &project.repo_ref.revision)
             .await?
     };
 
@@ -54,6 +110,11 @@ pub async fn update_lock(project: &Project, lock: &Option<Lock>) -> Result<(Lock
         return Ok((lock.clone().unwrap(), false));
     }
 
+    if let Some(cached_lock) = cache.get(&project.repo_ref, &current_commit) {
+        eprintln!("Reusing cached lock for `{}`, revision {}", project.repo_ref.repo_url, current_commit);
+        return Ok((cached_lock.clone(), true));
+    }
+
     let fetch_output = nix_prefetch_git(
         &project.repo_ref.repo_url,
         &current_commit,
@@ -66,12 +127,7 @@ pub async fn update_lock(project: &Project, lock: &Option<Lock>) -> Result<(Lock
         return Err(UpdateLockError::CommitMismatch(project.repo_ref.revision.clone()));
     }
 
-    Ok((Lock {
-        commit: fetch_output.rev,
-        nix_hash: fetch_output.hash,
-        path: fetch_output.path,
-        date: fetch_output.date,
-    }, true))
+    Ok((fetch_output.into(), true))
 }
 
 
@@ -79,12 +135,28 @@ pub async fn update_lock(project: &Project, lock: &Option<Lock>) -> Result<(Lock
 pub struct LocksetEntry {
     pub project: Project,
     pub lock: Option<Lock>,
+    /// The override from the overrides file the project was locked with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied_override: Option<AppliedOverride>,
+    /// The verified signature of the project's tag, if signatures were verified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<VerifiedSignature>,
+    /// The fetch options detected from the project's contents, if `--detect-fetch-options` was
+    /// used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detected_fetch_options: Option<DetectedFetchOptions>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Lockset {
     pub entries: BTreeMap<PathBuf, LocksetEntry>,
     pub path: PathBuf,
+    #[serde(default)]
+    pub manifest: Option<ManifestLock>,
+    #[serde(default)]
+    pub extra_manifests: Vec<ManifestLock>,
+    #[serde(skip)]
+    pub cache: LockCache,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -92,9 +164,32 @@ pub struct Lockfile {
     // BTreeMap because we want the ordering in the serialized lockfile to be consistent across
     // runs
     pub fetch_completed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<ManifestLock>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_manifests: Vec<ManifestLock>,
     pub entries: BTreeMap<PathBuf, LocksetEntry>,
 }
 
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A lock recorded in the journal of a lockfile. The repo ref is recorded as well, so that locks
+/// of projects that changed in the meantime aren't replayed.
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalRecord {
+    path: PathBuf,
+    repo_ref: GitRepoRef,
+    lock: Lock,
+}
+
+/// The append-only journal next to a lockfile, which records new locks as they are computed
+/// instead of rewriting the whole lockfile every time.
+pub fn journal_path(lockfile_path: &Path) -> PathBuf {
+    let mut path = lockfile_path.as_os_str().to_owned();
+    path.push(".journal");
+    PathBuf::from(path)
+}
+
 #[derive(Debug, Error)]
 pub enum UpdateLocksetError {
     #[error("failed to update lock for `{project_path}`")]
@@ -129,6 +224,8 @@ pub enum EnsureStorePathError {
     StorePathIO(#[from] io::Error),
     #[error("error running nix-prefetch-git")]
     NixPrefetchGit(#[from] NixPrefetchGitError),
+    #[error("`{1}` of project {0} points to {2} instead of the locked commit")]
+    RevisionMoved(PathBuf, String, String),
 }
 
 impl Lockset {
@@ -138,10 +235,26 @@ impl Lockset {
                 .iter()
                 .map(|(path, project)| (path.clone(), LocksetEntry {
                     project: project.clone(),
-                    lock: None
+                    lock: None,
+                    applied_override: None,
+                    signature: None,
+                    detected_fetch_options: None,
                 }))
                 .collect(),
             path: path.to_path_buf(),
+            manifest: None,
+            extra_manifests: vec![],
+            cache: LockCache::default(),
+        }
+    }
+
+    /// Adds all locks of this lockset to its lock cache, so that locks of inactive or moved
+    /// projects can be reused.
+    pub fn cache_own_locks(&mut self) {
+        for entry in self.entries.values() {
+            if let Some(lock) = &entry.lock {
+                self.cache.insert(&entry.project.repo_ref, lock);
+            }
         }
     }
 
@@ -185,6 +298,10 @@ impl Lockset {
                     if entry.project.repo_ref != project.repo_ref {
                         entry.lock = None;
                     }
+                    let old_ref = &entry.project.repo_ref;
+                    if old_ref.repo_url != project.repo_ref.repo_url || old_ref.revision != project.repo_ref.revision {
+                        entry.detected_fetch_options = None;
+                    }
                     entry.project = project;
                 }
             },
@@ -192,6 +309,9 @@ impl Lockset {
                 self.entries.insert(project.path.clone(), LocksetEntry {
                     project: project,
                     lock: None,
+                    applied_override: None,
+                    signature: None,
+                    detected_fetch_options: None,
                 });
             },
         }
@@ -202,21 +322,113 @@ impl Lockset {
     pub async fn read_from_file(path: &Path) -> Result<Self, ReadWriteLockfileError> {
         let json = fs::read(path).await.map_err(ReadWriteLockfileError::IO)?;
         let lockfile: Lockfile = serde_json::from_reader(json.as_slice()).map_err(ReadWriteLockfileError::Parse)?;
-        Ok(Lockset {
+        let mut lockset = Lockset {
             entries: lockfile.entries,
             path: path.to_path_buf(),
-        })
+            manifest: lockfile.manifest,
+            extra_manifests: lockfile.extra_manifests,
+            cache: LockCache::default(),
+        };
+        lockset.replay_journal().await?;
+        Ok(lockset)
+    }
+
+    /// Applies the locks recorded in the journal by an earlier, interrupted run. All recorded
+    /// locks are added to the lock cache as well, so that they can be reused even if the project
+    /// was changed since by a `fetch` that got interrupted.
+    async fn replay_journal(&mut self) -> Result<(), ReadWriteLockfileError> {
+        let journal = match fs::read(journal_path(&self.path)).await {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let lines: Vec<_> = journal.split(|x| *x == b'\n').filter(|x| !x.is_empty()).collect();
+        for (i, line) in lines.iter().enumerate() {
+            let record: JournalRecord = match serde_json::from_slice(line) {
+                Ok(record) => record,
+                // The last record may be incomplete if the process was killed while writing it.
+                Err(_) if i == lines.len() - 1 => break,
+                Err(e) => return Err(e.into()),
+            };
+            self.cache.insert(&record.repo_ref, &record.lock);
+            if let Some(entry) = self.entries.get_mut(&record.path) {
+                if entry.project.repo_ref == record.repo_ref {
+                    entry.lock = Some(record.lock);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn append_to_journal(&self, path: &Path, repo_ref: &GitRepoRef, lock: &Lock) -> Result<(), ReadWriteLockfileError> {
+        let mut line = serde_json::to_vec(&JournalRecord {
+            path: path.to_path_buf(),
+            repo_ref: repo_ref.clone(),
+            lock: lock.clone(),
+        })?;
+        line.push(b'\n');
+
+        let path = journal_path(&self.path);
+        let mut journal = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .await?;
+
+        // Cut off a record torn by an earlier crash. Otherwise the new record would be appended
+        // to it, and the torn record would no longer be the last one, which replaying skips.
+        if journal.metadata().await?.len() > 0 {
+            journal.seek(SeekFrom::End(-1)).await?;
+            if journal.read_u8().await? != b'\n' {
+                let contents = fs::read(&path).await?;
+                let complete_len = contents.iter().rposition(|x| *x == b'\n').map_or(0, |i| i + 1);
+                journal.set_len(complete_len as u64).await?;
+            }
+        }
+        journal.write_all(&line).await?;
+        journal.sync_data().await?;
+        Ok(())
     }
 
     pub async fn write(&self, fetch_completed: bool) -> Result<(), ReadWriteLockfileError> {
         let json = serde_json::to_vec_pretty(&Lockfile {
             entries: self.entries.clone(),
+            manifest: self.manifest.clone(),
+            extra_manifests: self.extra_manifests.clone(),
             fetch_completed: fetch_completed,
         }).map_err(ReadWriteLockfileError::Parse)?;
-        let tmp_path = self.path.with_extension(".tmp");
-        fs::write(&tmp_path, json.as_slice()).await?;
-        fs::rename(&tmp_path, &self.path).await?;
-        Ok(())
+        // A unique temporary file, so that concurrent writers never clobber each other's
+        // half-written files.
+        let mut tmp_path = self.path.as_os_str().to_owned();
+        tmp_path.push(format!(".{}.{}.tmp", process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let tmp_path = PathBuf::from(tmp_path);
+        let result = async {
+            let mut file = fs::File::create(&tmp_path).await?;
+            file.write_all(&json).await?;
+            file.sync_all().await?;
+            fs::rename(&tmp_path, &self.path).await?;
+            // Make the rename itself durable.
+            let parent = self.path.parent().filter(|x| !x.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            fs::File::open(parent).await?.sync_all().await
+        }.await;
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path).await;
+        }
+        result?;
+
+        // Only a completed fetch has everything recorded in the journal in the lockfile. Before
+        // that, the journal may still hold locks of projects that changed since, which are only
+        // in the lock cache and would be lost if the run is interrupted again.
+        if !fetch_completed {
+            return Ok(());
+        }
+        match fs::remove_file(journal_path(&self.path)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
     }
 
     pub async fn update(&mut self, project_path: &Path) -> Result<(), UpdateLocksetError> {
@@ -224,7 +436,8 @@ impl Lockset {
         let (new_lock, updated) =
             update_lock(
                 &entry.project,
-                &entry.lock
+                &entry.lock,
+                &self.cache,
             )
             .await
             .map_err(|e| UpdateLocksetError::UpdateLock {
@@ -232,10 +445,15 @@ impl Lockset {
                 error: e,
             })?;
 
-        entry.lock = Some(new_lock);
+        self.cache.insert(&entry.project.repo_ref, &new_lock);
+        let repo_ref = entry.project.repo_ref.clone();
+        if entry.lock.as_ref() != Some(&new_lock) {
+            entry.signature = None;
+        }
+        entry.lock = Some(new_lock.clone());
 
         if updated {
-            self.write(false).await?;
+            self.append_to_journal(project_path, &repo_ref, &new_lock).await?;
         }
 
         Ok(())
@@ -258,14 +476,152 @@ impl Lockset {
         let lock = &entry.lock.as_ref().ok_or(EnsureStorePathError::ProjectNotLocked(project_path.to_path_buf()))?;
 
         if !fs::try_exists(&lock.path).await? {
-            nix_prefetch_git(
+            let revision = lock.prefetched_revision(repo_ref);
+            let output = nix_prefetch_git(
                 &repo_ref.repo_url,
-                &lock.commit,
+                revision,
                 repo_ref.fetch_lfs,
                 repo_ref.fetch_submodules,
             ).await?;
+            if output.rev != lock.commit {
+                return Err(EnsureStorePathError::RevisionMoved(project_path.to_path_buf(), revision.to_string(), output.rev));
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_project, TempDir};
+
+    #[tokio::test]
+    async fn update_lock_reuses_cached_lock() {
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let project = test_project("build/make", "https://android.googlesource.com/platform/build", commit);
+        let cached = Lock {
+            commit: commit.to_string(),
+            nix_hash: "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A=".to_string(),
+            path: PathBuf::from("/nix/store/caphp23qx36iqbpcfa0wx1q16h8sq65x-build-0123456"),
+            date: 1,
+        };
+        let mut cache = LockCache::default();
+        cache.insert(&project.repo_ref, &cached);
+
+        let (lock, updated) = update_lock(&project, &None, &cache).await.unwrap();
+        assert!(updated);
+        assert_eq!(lock.nix_hash, cached.nix_hash);
+
+        // Different fetch options must not hit the cache entry.
+        let mut submodule_project = project.clone();
+        submodule_project.repo_ref.fetch_submodules = true;
+        assert!(cache.get(&submodule_project.repo_ref, commit).is_none());
+    }
+
+    #[tokio::test]
+    async fn manifest_lock_roundtrip() {
+        let tmp = TempDir::new("manifest-lock");
+        let path = tmp.path().join("test.lock");
+
+        // Lockfiles from before manifest locking don't have a manifest section.
+        fs::write(&path, r#"{ "fetch_completed": true, "entries": {} }"#).await.unwrap();
+        let mut lockset = Lockset::read_from_file(&path).await.unwrap();
+        assert!(lockset.manifest.is_none());
+        assert!(lockset.extra_manifests.is_empty());
+
+        let manifest = ManifestLock {
+            url: GitUrl::parse("git@example.com:vendor/manifest").unwrap(),
+            git_ref: Some("refs/heads/main".to_string()),
+            manifest_name: PathBuf::from("snippets/pixel.xml"),
+            lock: Some(Lock {
+                commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+                nix_hash: "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A=".to_string(),
+                path: PathBuf::from("/nix/store/caphp23qx36iqbpcfa0wx1q16h8sq65x-manifest-0123456"),
+                date: 1,
+            }),
+            signature: None,
+        };
+        lockset.manifest = Some(manifest.clone());
+        lockset.write(true).await.unwrap();
+
+        let lockset = Lockset::read_from_file(&path).await.unwrap();
+        assert_eq!(lockset.manifest, Some(manifest));
+    }
+
+    #[tokio::test]
+    async fn replay_journal_on_resume() {
+        let tmp = TempDir::new("journal");
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let projects = HashMap::from(["build/make", "art"].map(|path| {
+            (PathBuf::from(path), test_project(path, &format!("https://example.com/{path}"), commit))
+        }));
+        let mut lockset = Lockset::new(&projects, &tmp.path().join("test.lock"));
+        lockset.write(false).await.unwrap();
+
+        let lock = Lock {
+            commit: commit.to_string(),
+            nix_hash: "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A=".to_string(),
+            path: PathBuf::from("/nix/store/caphp23qx36iqbpcfa0wx1q16h8sq65x-build-0123456"),
+            date: 1,
+        };
+        lockset.cache.insert(&lockset.entries[Path::new("build/make")].project.repo_ref, &lock);
+        lockset.update(Path::new("build/make")).await.unwrap();
+
+        // Simulate a crash in the middle of writing the next record.
+        let journal = journal_path(&lockset.path);
+        let mut text = std::fs::read_to_string(&journal).unwrap();
+        text += r#"{"path":"art","repo_ref":{"#;
+        std::fs::write(&journal, text).unwrap();
+
+        let resumed = Lockset::read_from_file(&lockset.path).await.unwrap();
+        assert_eq!(resumed.entries[Path::new("build/make")].lock, Some(lock.clone()));
+        assert_eq!(resumed.cache.get(&resumed.entries[Path::new("build/make")].project.repo_ref, commit), Some(&lock));
+        assert!(resumed.entries[Path::new("art")].lock.is_none());
+
+        // Intermediate writes keep the journal, so that its locks survive another crash.
+        resumed.write(false).await.unwrap();
+        assert!(journal.exists());
+        let resumed_again = Lockset::read_from_file(&lockset.path).await.unwrap();
+        assert_eq!(resumed_again.cache.get(&resumed.entries[Path::new("build/make")].project.repo_ref, commit), Some(&lock));
+        resumed.write(true).await.unwrap();
+        assert!(!journal.exists());
+        let compacted = Lockset::read_from_file(&lockset.path).await.unwrap();
+        assert!(compacted.entries[Path::new("build/make")].lock.is_some());
+    }
+
+    #[tokio::test]
+    async fn resume_after_repeated_crashes() {
+        let tmp = TempDir::new("journal-crashes");
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let projects = HashMap::from(["build/make", "art", "bionic"].map(|path| {
+            (PathBuf::from(path), test_project(path, &format!("https://example.com/{path}"), commit))
+        }));
+        let lockset = Lockset::new(&projects, &tmp.path().join("test.lock"));
+        lockset.write(false).await.unwrap();
+        let journal = journal_path(&lockset.path);
+        let lock = |name: &str| Lock {
+            commit: commit.to_string(),
+            nix_hash: "sha256-9mng1bJ/AqxmI8ctMs0fRGUCoo61Tj80obgbBeoeZ7A=".to_string(),
+            path: PathBuf::from(format!("/nix/store/caphp23qx36iqbpcfa0wx1q16h8sq65x-{name}-0123456")),
+            date: 1,
+        };
+
+        // Every run locks one project and crashes while writing the record of the next one.
+        let mut lockset = lockset;
+        for path in ["build/make", "art"] {
+            lockset.cache.insert(&lockset.entries[Path::new(path)].project.repo_ref, &lock(path));
+            lockset.update(Path::new(path)).await.unwrap();
+            let mut file = std::fs::OpenOptions::new().append(true).open(&journal).unwrap();
+            std::io::Write::write_all(&mut file, br#"{"path":"bionic","repo_ref":{"#).unwrap();
+            lockset = Lockset::read_from_file(&lockset.path).await.unwrap();
+        }
+
+        assert_eq!(lockset.entries[Path::new("build/make")].lock, Some(lock("build/make")));
+        assert_eq!(lockset.entries[Path::new("art")].lock, Some(lock("art")));
+        assert!(lockset.entries[Path::new("bionic")].lock.is_none());
+        let text = std::fs::read_to_string(&journal).unwrap();
+        assert_eq!(text.lines().filter(|x| serde_json::from_str::<JournalRecord>(x).is_err()).count(), 1);
+    }
+}
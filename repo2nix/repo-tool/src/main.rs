@@ -3,7 +3,7 @@ use std::io;
 use std::collections::{BTreeMap, BTreeSet};
 use std::io::ErrorKind;
 use clap::Parser;
-use url::Url;
+use repo_manifest::git_url::{GitUrl, ParseGitUrlError};
 use tokio::{self, fs};
 use serde_json;
 use repo_manifest::xml::{
@@ -25,11 +25,51 @@ use crate::fetch::{
     GitLsRemoteError,
 };
 use crate::lock::{
+    is_commit_id,
+    Lock,
     Lockset,
+    ManifestLock,
     ReadWriteLockfileError,
     UpdateLocksetError,
     EnsureStorePathError,
 };
+use crate::cache::BuildLockCacheError;
+use crate::file_lock::{
+    LockfileLock,
+    LockLockfileError,
+};
+use crate::manifest::{
+    fetch_manifest_repo,
+    FetchManifestRepoError,
+};
+use crate::detect::{
+    carry_over_fetch_options,
+    update_detected_fetch_options,
+    DetectFetchOptionsError,
+};
+use crate::rules::{
+    FetchRules,
+    ProjectRule,
+    ReadFetchRulesError,
+};
+use crate::override_tag::{
+    apply_override_tag,
+    OverrideTagError,
+};
+use crate::prune::{
+    referenced_locks,
+    prune_inactive,
+};
+use crate::overrides::{
+    read_overrides,
+    apply_overrides,
+    ReadOverridesError,
+};
+use crate::repo_prop::{
+    read_repo_prop,
+    apply_repo_prop,
+    ReadRepoPropError,
+};
 use crate::lineage_devices::DeviceInfo;
 use crate::lineage_dependencies::{
     merge_lineage_devices,
@@ -38,6 +78,14 @@ use crate::lineage_dependencies::{
     PrefetchLineageDepsError,
     cleanup_failed_lineage_deps,
 };
+use crate::signatures::{
+    verify_ref_signature,
+    verify_project_signatures,
+    TrustedKeys,
+    LoadTrustedKeysError,
+    VerifySignatureError,
+    VerifyProjectSignatureError,
+};
 use crate::utils::{
     tag_device_by_group,
     cleanup_broken_projects,
@@ -47,34 +95,151 @@ use main_error::MainError;
 
 mod fetch;
 mod lock;
+mod file_lock;
+mod manifest;
+mod cache;
+mod repo_prop;
+mod override_tag;
+mod rules;
+mod overrides;
+mod detect;
+#[cfg(test)]
+mod test_utils;
 mod lineage_devices;
 mod lineage_dependencies;
 mod utils;
 mod graphene;
 mod graphene_vendor;
+mod hash;
+mod legacy;
+mod diff;
+mod changelog;
+mod git_cache;
+mod outdated;
+mod verify;
+mod nix_export;
+mod sbom;
+mod query;
+mod stale;
+mod signatures;
+mod update;
+mod prune;
+
+#[derive(clap::Args)]
+struct FetchArgs {
+    manifest_url: String,
+    lockfile_path: PathBuf,
 
-#[derive(Parser)]
-enum Args {
-    Fetch {
-        manifest_url: String,
-        lockfile_path: PathBuf,
+    /// The branch (or tag if `--tag` is given) of the manifest repo to fetch. Can only be
+    /// omitted for local manifest repos, which are then used as they are.
+    #[arg(long, short)]
+    revision: Option<String>,
 
-        #[arg(long, short)]
-        revision: String,
+    /// The manifest file to read from the manifest repo.
+    #[arg(long, short, default_value = "default.xml")]
+    manifest_name: PathBuf,
 
-        // Interpret the `revision` argument as a git tag instead of a git branch.
-        #[arg(long, short)]
-        tag: bool,
+    /// Fetch this exact commit of the manifest repo, e.g. to regenerate a lockfile from the
+    /// manifest commit recorded in it.
+    #[arg(long)]
+    manifest_commit: Option<String>,
 
-        #[arg(long, short)]
-        lineage_device_file: Vec<PathBuf>,
+    // Interpret the `revision` argument as a git tag instead of a git branch.
+    #[arg(long, short)]
+    tag: bool,
 
-        #[arg(long, short)]
-        missing_dep_devs_file: Option<PathBuf>,
+    #[arg(long, short)]
+    lineage_device_file: Vec<PathBuf>,
 
-        #[arg(long)]
-        muppets: bool,
-    },
+    #[arg(long)]
+    missing_dep_devs_file: Option<PathBuf>,
+
+    #[arg(long)]
+    muppets: bool,
+
+    /// Reuse locks from all lockfiles found in this directory (or file).
+    #[arg(long)]
+    cache_search_path: Vec<PathBuf>,
+
+    /// Pin project revisions to the commits listed in this `repo.prop` file.
+    #[arg(long)]
+    repo_prop: Option<PathBuf>,
+
+    /// Fetch this tag for all projects instead of the revisions from the manifest.
+    #[arg(long)]
+    override_tag: Option<String>,
+
+    /// Only apply `--override-tag` to projects from this remote.
+    #[arg(long, requires = "override_tag")]
+    override_tag_remote: Vec<String>,
+
+    /// Apply the per-project URL, revision and fetch option overrides from this JSON file.
+    #[arg(long)]
+    overrides: Option<PathBuf>,
+
+    /// Read project include/exclude rules and per-project fetch options from this JSON file.
+    #[arg(long)]
+    rules: Option<PathBuf>,
+
+    /// Only lock projects whose path matches this glob pattern.
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Don't lock projects whose path matches this glob pattern.
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Don't fetch Git LFS objects, unless enabled for a project in the rules file.
+    #[arg(long)]
+    disable_lfs: bool,
+
+    /// Don't fetch Git LFS objects for projects whose path matches this glob pattern.
+    #[arg(long)]
+    project_disable_lfs: Vec<String>,
+
+    /// Fetch submodules for projects whose path matches this glob pattern.
+    #[arg(long)]
+    project_fetch_submodules: Vec<String>,
+
+    /// Enable LFS and submodule fetching based on the contents of the locked projects.
+    #[arg(long)]
+    detect_fetch_options: bool,
+
+    /// Remove entries of projects that aren't part of the manifest anymore.
+    #[arg(long)]
+    prune: bool,
+
+    /// Keep entries removed from the manifest if some lockfile in this directory (or file)
+    /// references their lock.
+    #[arg(long, requires = "prune")]
+    prune_keep_referenced: Vec<PathBuf>,
+
+    /// Trust the OpenPGP keys in this file for signature verification.
+    #[arg(long)]
+    trusted_keys: Vec<PathBuf>,
+
+    /// Trust the SSH keys in this allowed signers file for signature verification.
+    #[arg(long)]
+    allowed_signers: Option<PathBuf>,
+
+    /// Fail unless the manifest tag (or commit, for branches) is signed by a trusted key.
+    #[arg(long)]
+    verify_manifest_signature: bool,
+
+    /// Fail unless the tag (or commit, for other revisions) of every locked project is signed by
+    /// a trusted key.
+    #[arg(long)]
+    verify_project_signatures: bool,
+
+    /// Keep the bare repos used to verify signatures in this directory (defaults to
+    /// `$XDG_CACHE_HOME/repo-tool/git`).
+    #[arg(long)]
+    git_cache: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+enum Args {
+    Fetch(Box<FetchArgs>),
     GetLineageDevices {
         device_metadata_file: PathBuf,
 
@@ -106,16 +271,182 @@ enum Args {
         lockfile_path: PathBuf,
         store_paths: Option<Vec<PathBuf>>,
     },
+    ImportLegacy {
+        legacy_file: PathBuf,
+        lockfile_path: PathBuf,
+
+        /// Don't fetch Git LFS objects for the imported projects.
+        #[arg(long)]
+        disable_lfs: bool,
+    },
+    Diff {
+        old_lockfile: PathBuf,
+        new_lockfile: PathBuf,
+
+        /// Print the differences as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    Changelog {
+        old_lockfile: PathBuf,
+        new_lockfile: PathBuf,
+
+        /// Print the changelog as JSON instead of Markdown.
+        #[arg(long)]
+        json: bool,
+
+        /// Keep the bare repos used to look up commits in this directory (defaults to
+        /// `$XDG_CACHE_HOME/repo-tool/git`).
+        #[arg(long)]
+        git_cache: Option<PathBuf>,
+    },
+    Update {
+        lockfile_path: PathBuf,
+
+        /// Only update projects whose path matches one of these glob patterns.
+        patterns: Vec<String>,
+
+        /// Switch the selected projects to this revision (a full ref like `refs/heads/main`, or
+        /// a commit) before updating them.
+        #[arg(long, short)]
+        revision: Option<String>,
+    },
+    Prune {
+        lockfile_path: PathBuf,
+
+        /// Keep inactive entries if some lockfile in this directory (or file) references their
+        /// lock.
+        #[arg(long)]
+        keep_referenced: Vec<PathBuf>,
+    },
+    Outdated {
+        lockfile_path: PathBuf,
+
+        /// Print the outdated projects as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    ExportNix {
+        lockfile_path: PathBuf,
+
+        /// Write the Nix expression to this file instead of stdout.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    Query {
+        lockfile_path: PathBuf,
+
+        /// Only select projects whose path matches one of these glob patterns.
+        patterns: Vec<String>,
+
+        /// Only select projects in one of these categories (`default` or `device:<device>`).
+        #[arg(long, value_parser = utils::parse_category)]
+        category: Vec<Category>,
+
+        /// Only select projects in one of these groups.
+        #[arg(long)]
+        group: Vec<String>,
+
+        /// Only select projects hosted on one of these hosts.
+        #[arg(long)]
+        host: Vec<String>,
+
+        /// Only select active projects.
+        #[arg(long, conflicts_with = "inactive")]
+        active: bool,
+
+        /// Only select inactive projects.
+        #[arg(long)]
+        inactive: bool,
+
+        /// Only select locked projects.
+        #[arg(long, conflicts_with = "unlocked")]
+        locked: bool,
+
+        /// Only select unlocked projects.
+        #[arg(long)]
+        unlocked: bool,
+
+        /// The fields to print for each selected project.
+        #[arg(long = "field", short, value_enum, value_delimiter = ',', default_values_t = [query::QueryField::Path])]
+        fields: Vec<query::QueryField>,
+
+        /// Print the selected projects as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    Stale {
+        lockfile_path: PathBuf,
+
+        /// Report projects whose locked commit is older than this many days.
+        #[arg(long, default_value_t = 365)]
+        max_age: u64,
+
+        /// Also report projects whose tracked revision has newer commits than the locked one.
+        #[arg(long)]
+        check_remote: bool,
+
+        /// Print the report as JSON.
+        #[arg(long)]
+        json: bool,
+
+        /// Keep the bare repos used to look up commits in this directory (defaults to
+        /// `$XDG_CACHE_HOME/repo-tool/git`).
+        #[arg(long)]
+        git_cache: Option<PathBuf>,
+    },
+    Sbom {
+        lockfile_path: PathBuf,
+
+        /// The SBOM format to generate.
+        #[arg(long, value_enum, default_value_t = sbom::SbomFormat::Spdx)]
+        format: sbom::SbomFormat,
+
+        /// Write the SBOM to this file instead of stdout.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    Verify {
+        lockfile_path: PathBuf,
+
+        /// Also prefetch every project again and compare its NAR hash to the locked one.
+        #[arg(long)]
+        check_hashes: bool,
+
+        /// Print the report as JSON.
+        #[arg(long)]
+        json: bool,
+
+        /// Keep the bare repos used to look up commits in this directory (defaults to
+        /// `$XDG_CACHE_HOME/repo-tool/git`).
+        #[arg(long)]
+        git_cache: Option<PathBuf>,
+    },
 }
 
 #[derive(Debug, Error)]
 enum FetchError {
+    #[error("error locking lockfile")]
+    LockLockfile(#[from] LockLockfileError),
+
     #[error("error parsing manifest URL")]
-    UrlParse(#[from] url::ParseError),
+    UrlParse(#[from] ParseGitUrlError),
 
     #[error("error fetching manifest")]
     ManifestFetch(#[from] NixPrefetchGitError),
 
+    #[error("error fetching manifest repo")]
+    FetchManifestRepo(#[from] FetchManifestRepoError),
+
+    #[error("LineageOS-specific and Muppets fetching require a manifest revision")]
+    MissingRevision,
+
+    #[error("invalid manifest commit `{0}`")]
+    InvalidManifestCommit(String),
+
+    #[error("`--manifest-commit` with `--muppets` requires a Muppets manifest commit recorded in the lockfile")]
+    MissingMuppetsCommit,
+
     #[error("error reading manifest files")]
     ReadManifest(#[source] RecursivelyReadManifestFilesError),
 
@@ -160,17 +491,75 @@ enum FetchError {
 
     #[error("error updating lockfile")]
     UpdateLockset(#[source] UpdateLocksetError),
+
+    #[error("error loading cached locks")]
+    BuildLockCache(#[from] BuildLockCacheError),
+
+    #[error("error reading repo.prop file")]
+    ReadRepoProp(#[from] ReadRepoPropError),
+
+    #[error("error applying override tag")]
+    OverrideTag(#[from] OverrideTagError),
+
+    #[error("error reading overrides file")]
+    ReadOverrides(#[from] ReadOverridesError),
+
+    #[error("error reading fetch rules")]
+    ReadFetchRules(#[from] ReadFetchRulesError),
+
+    #[error("error detecting fetch options")]
+    DetectFetchOptions(#[from] DetectFetchOptionsError),
+
+    #[error("signature verification requires `--trusted-keys` or `--allowed-signers`")]
+    MissingTrustedKeys,
+
+    #[error("error loading trusted keys")]
+    LoadTrustedKeys(#[from] LoadTrustedKeysError),
+
+    #[error("can't verify the signature of a local manifest repo without a revision")]
+    UnlockedManifest,
+
+    #[error("error verifying manifest signature")]
+    VerifyManifestSignature(#[source] VerifySignatureError),
+
+    #[error("error verifying project signatures")]
+    VerifyProjectSignatures(#[from] VerifyProjectSignatureError),
 }
 
-async fn fetch(
-    manifest_url: String,
-    lockfile_path: PathBuf,
-    revision: String,
-    tag: bool,
-    lineage_device_file: Vec<PathBuf>,
-    missing_dep_devs_file: Option<PathBuf>,
-    muppets: bool,
-) -> Result<(), FetchError> {
+async fn fetch(args: FetchArgs) -> Result<(), FetchError> {
+    let FetchArgs {
+        manifest_url,
+        lockfile_path,
+        revision,
+        manifest_name,
+        manifest_commit,
+        tag,
+        lineage_device_file,
+        missing_dep_devs_file,
+        muppets,
+        cache_search_path,
+        repo_prop,
+        override_tag,
+        override_tag_remote,
+        overrides,
+        rules,
+        include,
+        exclude,
+        disable_lfs,
+        project_disable_lfs,
+        project_fetch_submodules,
+        detect_fetch_options,
+        prune,
+        prune_keep_referenced,
+        trusted_keys,
+        allowed_signers,
+        verify_manifest_signature,
+        verify_project_signatures: verify_projects,
+        git_cache,
+    } = args;
+
+    let _lockfile_lock = LockfileLock::acquire(&lockfile_path)?;
+
     if muppets || lineage_device_file.len() > 0 {
         assert!(
             missing_dep_devs_file.is_some(),
@@ -178,43 +567,161 @@ async fn fetch(
         );
     }
 
-    let url = Url::parse(&manifest_url)?;
-    let git_ref = if tag {
+    let keys = if verify_manifest_signature || verify_projects {
+        if trusted_keys.is_empty() && allowed_signers.is_none() {
+            return Err(FetchError::MissingTrustedKeys);
+        }
+        Some(TrustedKeys::load(&trusted_keys, allowed_signers.as_deref()).await?)
+    } else {
+        None
+    };
+    let git_cache = git_cache.unwrap_or_else(git_cache::default_git_cache_dir);
+
+    let url = GitUrl::parse(&manifest_url)?;
+    let git_ref = revision.as_ref().map(|revision| if tag {
         format!("refs/tags/{revision}")
     } else {
         format!("refs/heads/{revision}")
-    };
-    let manifest_fetch = nix_prefetch_git(
+    });
+    if let Some(commit) = &manifest_commit {
+        if !is_commit_id(commit) {
+            return Err(FetchError::InvalidManifestCommit(commit.clone()));
+        }
+    }
+    let (manifest_path, manifest_repo_lock) = fetch_manifest_repo(
         &url,
-        &git_ref,
-        false,
-        false,
+        manifest_commit.as_deref().or(git_ref.as_deref())
     ).await?;
+    if let Some(lock) = &manifest_repo_lock {
+        eprintln!("Using manifest commit {}", lock.commit);
+    }
+    let manifest_signature = match &keys {
+        Some(keys) if verify_manifest_signature => {
+            let lock = manifest_repo_lock.as_ref().ok_or(FetchError::UnlockedManifest)?;
+            let signed_ref = git_ref.as_deref().unwrap_or(&lock.commit);
+            let signature = verify_ref_signature(&git_cache, keys, &url, signed_ref, &lock.commit)
+                .await
+                .map_err(FetchError::VerifyManifestSignature)?;
+            eprintln!("Manifest signed by {} ({})", signature.signer, signature.key);
+            Some(signature)
+        },
+        _ => None,
+    };
+    let manifest_lock = ManifestLock {
+        url: url.clone(),
+        git_ref,
+        manifest_name: manifest_name.clone(),
+        lock: manifest_repo_lock,
+        signature: manifest_signature,
+    };
+    let mut extra_manifests = vec![];
 
-    let mut manifest_xml = recursively_read_manifest_files(
-        &manifest_fetch.path, Path::new("default.xml")
-    )
+    let mut manifest_xml = recursively_read_manifest_files(&manifest_path, &manifest_name)
         .await
         .map_err(FetchError::ReadManifest)?;
 
     if muppets {
-        let muppets_fetch = nix_prefetch_git(
-            &Url::parse("https://github.com/TheMuppets/manifests")?,
-            &format!("refs/heads/{revision}"),
+        let revision = revision.as_ref().ok_or(FetchError::MissingRevision)?;
+        let muppets_url = GitUrl::parse("https://github.com/TheMuppets/manifests")?;
+        let muppets_ref = format!("refs/heads/{revision}");
+        let muppets_name = PathBuf::from("muppets.xml");
+        // With `--manifest-commit`, use the Muppets commit recorded along with the manifest
+        // commit, so that the lockfile is regenerated from exactly the same manifests.
+        let muppets_rev = if manifest_commit.is_some() {
+            let recorded = match Lockset::read_from_file(&lockfile_path).await {
+                Ok(lockfile) => lockfile.extra_manifests
+                    .into_iter()
+                    .find(|x| x.url == muppets_url)
+                    .and_then(|x| x.lock),
+                Err(ReadWriteLockfileError::IO(e)) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(FetchError::ReadLockset(e)),
+            };
+            recorded.ok_or(FetchError::MissingMuppetsCommit)?.commit
+        } else {
+            muppets_ref.clone()
+        };
+        let muppets_lock: Lock = nix_prefetch_git(
+            &muppets_url,
+            &muppets_rev,
             false,
             false,
-        ).await?;
+        ).await?.into();
 
-        let muppets_manifest_xml = read_manifest_file(&muppets_fetch.path.join("muppets.xml"))
+        let muppets_manifest_xml = read_manifest_file(&muppets_lock.path.join(&muppets_name))
             .await
             .map_err(FetchError::ReadMuppetsManifest)?;
+        extra_manifests.push(ManifestLock {
+            url: muppets_url,
+            git_ref: Some(muppets_ref),
+            manifest_name: muppets_name,
+            lock: Some(muppets_lock),
+            signature: None,
+        });
         merge_manifests(&mut manifest_xml, &muppets_manifest_xml)
             .map_err(FetchError::MergeMuppetsManifest)?;
     }
-    let manifest = resolve_manifest(&manifest_xml, &url)?;
+    let mut manifest = resolve_manifest(&manifest_xml, &url)?;
+
+    let mut fetch_rules = FetchRules::default();
+    if disable_lfs {
+        fetch_rules.projects.push(ProjectRule {
+            path: "**".to_string(),
+            fetch_lfs: Some(false),
+            fetch_submodules: None,
+        });
+    }
+    if let Some(rules) = rules {
+        let file_rules = FetchRules::read_from_file(&rules).await?;
+        fetch_rules.include.extend(file_rules.include);
+        fetch_rules.exclude.extend(file_rules.exclude);
+        fetch_rules.projects.extend(file_rules.projects);
+    }
+    fetch_rules.include.extend(include);
+    fetch_rules.exclude.extend(exclude);
+    for path in project_disable_lfs {
+        fetch_rules.projects.push(ProjectRule { path, fetch_lfs: Some(false), fetch_submodules: None });
+    }
+    for path in project_fetch_submodules {
+        fetch_rules.projects.push(ProjectRule { path, fetch_lfs: None, fetch_submodules: Some(true) });
+    }
+    let excluded_projects = fetch_rules.apply(&mut manifest);
+    if !excluded_projects.is_empty() {
+        eprintln!("Excluded {} projects from locking.", excluded_projects.len());
+    }
+
+    if let Some(override_tag) = override_tag {
+        let fallback_projects = apply_override_tag(&mut manifest, &override_tag, &override_tag_remote).await?;
+        if !fallback_projects.is_empty() {
+            eprintln!("Projects without tag `{override_tag}`, using manifest revisions: {fallback_projects:?}");
+        }
+    }
+
+    if let Some(repo_prop) = repo_prop {
+        let revisions = read_repo_prop(&repo_prop).await?;
+        let report = apply_repo_prop(&mut manifest.projects, &revisions);
+        if !report.missing_projects.is_empty() {
+            eprintln!("Projects missing from repo.prop, using manifest revisions: {:?}", report.missing_projects);
+        }
+        if !report.unused_entries.is_empty() {
+            eprintln!("repo.prop entries not matching any manifest project: {:?}", report.unused_entries);
+        }
+    }
+
+    let applied_overrides = match overrides {
+        Some(overrides) => {
+            let overrides = read_overrides(&overrides).await?;
+            let (applied_overrides, unused) = apply_overrides(&mut manifest.projects, &overrides);
+            if !unused.is_empty() {
+                eprintln!("Overrides not matching any manifest project: {unused:?}");
+            }
+            applied_overrides
+        },
+        None => BTreeMap::new(),
+    };
 
     let mut lockfile = match Lockset::read_from_file(&lockfile_path).await {
         Ok(mut lf) => {
+            carry_over_fetch_options(&lf, &mut manifest, &fetch_rules, &applied_overrides);
             lf.deactivate_all();
             for project in manifest.projects.values() {
                 lf.add_project(project.clone())
@@ -236,6 +743,20 @@ async fn fetch(
         Err(e) => return Err(FetchError::ReadLockset(e)),
     };
 
+    for (path, entry) in lockfile.entries.iter_mut().filter(|(_, x)| x.project.active) {
+        entry.applied_override = applied_overrides.get(path).cloned();
+        entry.signature = None;
+    }
+    lockfile.manifest = Some(manifest_lock);
+    lockfile.extra_manifests = extra_manifests;
+    lockfile.cache_own_locks();
+    for path in cache_search_path.iter() {
+        lockfile.cache.add_search_path(path).await?;
+    }
+    if !cache_search_path.is_empty() {
+        eprintln!("Loaded {} cached locks.", lockfile.cache.len());
+    }
+
     let muppets_broken_devices = if muppets {
         tag_device_by_group(&mut lockfile, "muppets_");
         let broken_muppets_entries = cleanup_broken_projects(
@@ -260,6 +781,7 @@ async fn fetch(
 
 
     if lineage_device_file.len() > 0 {
+        let revision = revision.as_ref().ok_or(FetchError::MissingRevision)?;
         let mut all_devices = BTreeMap::new();
         for ldf in lineage_device_file {
             let devices: BTreeMap<String, DeviceInfo> = serde_json::from_slice(
@@ -274,7 +796,7 @@ async fn fetch(
             &mut lockfile,
             &all_devices,
             &manifest,
-            &revision
+            revision
         )
             .await
             .map_err(FetchError::PrefetchLineageDeps)?;
@@ -305,6 +827,33 @@ async fn fetch(
     }
 
     lockfile.update_all().await.map_err(FetchError::UpdateLockset)?;
+
+    if detect_fetch_options {
+        let changes = update_detected_fetch_options(&mut lockfile, &fetch_rules, &applied_overrides).await?;
+        for (path, (old, new)) in changes.iter() {
+            eprintln!(
+                "`{}`: fetch_lfs {} -> {}, fetch_submodules {} -> {}",
+                path.display(),
+                old.fetch_lfs,
+                new.fetch_lfs,
+                old.fetch_submodules,
+                new.fetch_submodules,
+            );
+        }
+        eprintln!("Changed fetch options of {} projects.", changes.len());
+    }
+
+    if prune {
+        let referenced = referenced_locks(&prune_keep_referenced, &lockfile_path).await?;
+        print_prune_report(&prune_inactive(&mut lockfile, &referenced));
+    }
+
+    if let Some(keys) = &keys {
+        if verify_projects {
+            verify_project_signatures(&mut lockfile, &git_cache, keys).await?;
+        }
+    }
+
     lockfile.write(true).await.map_err(FetchError::WriteLockset)?;
 
     Ok(())
@@ -491,30 +1040,320 @@ async fn ensure_store_paths(lockfile_path: PathBuf, store_paths: Option<Vec<Path
     Ok(())
 }
 
+#[derive(Debug, Error)]
+enum ImportLegacyError {
+    #[error("error locking lockfile")]
+    LockLockfile(#[from] LockLockfileError),
+
+    #[error("error importing legacy repo file")]
+    Import(#[from] legacy::ReadLegacyRepoFileError),
+
+    #[error("error writing lockfile")]
+    WriteLockset(#[from] ReadWriteLockfileError),
+}
+
+async fn import_legacy(legacy_file: PathBuf, lockfile_path: PathBuf, disable_lfs: bool) -> Result<(), ImportLegacyError> {
+    let _lockfile_lock = LockfileLock::acquire(&lockfile_path)?;
+    let lockfile = legacy::import_legacy_repo_file(&legacy_file, &lockfile_path, !disable_lfs).await?;
+
+    let unlocked: Vec<_> = lockfile.entries
+        .iter()
+        .filter(|(_, entry)| entry.lock.is_none())
+        .map(|(path, _)| path)
+        .collect();
+    if !unlocked.is_empty() {
+        eprintln!("Projects without a complete lock in the legacy file: {unlocked:?}");
+    }
+
+    lockfile.write(unlocked.is_empty()).await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum DiffError {
+    #[error("error reading lockfile `{0}`")]
+    ReadLockfile(PathBuf, #[source] ReadWriteLockfileError),
+
+    #[error("error serializing diff into JSON")]
+    Serialize(#[from] serde_json::Error),
+}
+
+async fn diff(old_lockfile: PathBuf, new_lockfile: PathBuf, json: bool) -> Result<(), DiffError> {
+    let old = Lockset::read_from_file(&old_lockfile)
+        .await
+        .map_err(|e| DiffError::ReadLockfile(old_lockfile, e))?;
+    let new = Lockset::read_from_file(&new_lockfile)
+        .await
+        .map_err(|e| DiffError::ReadLockfile(new_lockfile, e))?;
+
+    let diff = diff::diff_lockfiles(&old.entries, &new.entries);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        print!("{diff}");
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum ChangelogError {
+    #[error("error reading lockfile `{0}`")]
+    ReadLockfile(PathBuf, #[source] ReadWriteLockfileError),
+
+    #[error("error generating changelog")]
+    Changelog(#[from] changelog::ChangelogError),
+
+    #[error("error serializing changelog into JSON")]
+    Serialize(#[from] serde_json::Error),
+}
+
+async fn changelog(old_lockfile: PathBuf, new_lockfile: PathBuf, json: bool, git_cache: Option<PathBuf>) -> Result<(), ChangelogError> {
+    let old = Lockset::read_from_file(&old_lockfile)
+        .await
+        .map_err(|e| ChangelogError::ReadLockfile(old_lockfile, e))?;
+    let new = Lockset::read_from_file(&new_lockfile)
+        .await
+        .map_err(|e| ChangelogError::ReadLockfile(new_lockfile, e))?;
+
+    let diff = diff::diff_lockfiles(&old.entries, &new.entries);
+    let git_cache = git_cache.unwrap_or_else(git_cache::default_git_cache_dir);
+    let changelogs = changelog::changelog(&diff, &old.entries, &new.entries, &git_cache).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&changelogs)?);
+    } else {
+        print!("{}", changelog::render_markdown(&changelogs));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum UpdateError {
+    #[error("error locking lockfile")]
+    LockLockfile(#[from] LockLockfileError),
+
+    #[error("error reading lockfile")]
+    ReadLockfile(#[source] ReadWriteLockfileError),
+
+    #[error("error updating projects")]
+    UpdateProjects(#[from] update::UpdateProjectsError),
+
+    #[error("error writing lockfile")]
+    WriteLockfile(#[source] ReadWriteLockfileError),
+}
+
+async fn update(lockfile_path: PathBuf, patterns: Vec<String>, revision: Option<String>) -> Result<(), UpdateError> {
+    let _lockfile_lock = LockfileLock::acquire(&lockfile_path)?;
+    let mut lockfile = Lockset::read_from_file(&lockfile_path)
+        .await
+        .map_err(UpdateError::ReadLockfile)?;
+    let updated = update::update_projects(&mut lockfile, &patterns, revision.as_deref()).await?;
+    eprintln!("Updated {} projects.", updated.len());
+
+    let fetch_completed = lockfile.entries.values().all(|x| !x.project.active || x.lock.is_some());
+    lockfile.write(fetch_completed).await.map_err(UpdateError::WriteLockfile)?;
+
+    Ok(())
+}
+
+fn print_prune_report(report: &prune::PruneReport) {
+    for path in report.removed.iter() {
+        eprintln!("Removed inactive entry `{}`", path.display());
+    }
+    if !report.kept.is_empty() {
+        eprintln!("Kept inactive entries referenced by other lockfiles: {:?}", report.kept);
+    }
+    eprintln!("Removed {} inactive entries.", report.removed.len());
+}
+
+#[derive(Debug, Error)]
+enum PruneError {
+    #[error("error locking lockfile")]
+    LockLockfile(#[from] LockLockfileError),
+
+    #[error("error reading lockfile")]
+    ReadLockfile(#[source] ReadWriteLockfileError),
+
+    #[error("error loading referenced locks")]
+    ReferencedLocks(#[from] BuildLockCacheError),
+
+    #[error("error writing lockfile")]
+    WriteLockfile(#[source] ReadWriteLockfileError),
+}
+
+async fn prune(lockfile_path: PathBuf, keep_referenced: Vec<PathBuf>) -> Result<(), PruneError> {
+    let _lockfile_lock = LockfileLock::acquire(&lockfile_path)?;
+    let mut lockfile = Lockset::read_from_file(&lockfile_path)
+        .await
+        .map_err(PruneError::ReadLockfile)?;
+    let referenced = referenced_locks(&keep_referenced, &lockfile_path).await?;
+    print_prune_report(&prune_inactive(&mut lockfile, &referenced));
+
+    let fetch_completed = lockfile.entries.values().all(|x| !x.project.active || x.lock.is_some());
+    lockfile.write(fetch_completed).await.map_err(PruneError::WriteLockfile)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum OutdatedError {
+    #[error("error reading lockfile")]
+    ReadLockfile(#[from] ReadWriteLockfileError),
+
+    #[error("error checking for updates")]
+    CheckOutdated(#[from] outdated::CheckOutdatedError),
+
+    #[error("error serializing report into JSON")]
+    Serialize(#[from] serde_json::Error),
+}
+
+async fn outdated(lockfile_path: PathBuf, json: bool) -> Result<(), OutdatedError> {
+    let lockfile = Lockset::read_from_file(&lockfile_path).await?;
+    let report = outdated::check_outdated(&lockfile).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum ExportNixError {
+    #[error("error reading lockfile")]
+    ReadLockfile(#[from] ReadWriteLockfileError),
+
+    #[error("error writing `{0}`")]
+    Write(PathBuf, #[source] std::io::Error),
+}
+
+async fn export_nix(lockfile_path: PathBuf, output: Option<PathBuf>) -> Result<(), ExportNixError> {
+    let lockfile = Lockset::read_from_file(&lockfile_path).await?;
+    let expr = nix_export::export_nix(&lockfile, &lockfile_path);
+    match output {
+        Some(output) => fs::write(&output, expr).await.map_err(|e| ExportNixError::Write(output, e))?,
+        None => print!("{expr}"),
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum QueryError {
+    #[error("error reading lockfile")]
+    ReadLockfile(#[from] ReadWriteLockfileError),
+
+    #[error("error serializing results into JSON")]
+    Serialize(#[from] serde_json::Error),
+}
+
+async fn query(lockfile_path: PathBuf, filter: query::QueryFilter, fields: Vec<query::QueryField>, json: bool) -> Result<(), QueryError> {
+    let lockfile = Lockset::read_from_file(&lockfile_path).await?;
+    let results = query::query(&lockfile, &filter, &fields);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        print!("{}", query::render_text(&results, &fields));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum StaleError {
+    #[error("error reading lockfile")]
+    ReadLockfile(#[from] ReadWriteLockfileError),
+
+    #[error("error looking for stale projects")]
+    FindStaleProjects(#[from] stale::FindStaleProjectsError),
+
+    #[error("error serializing report into JSON")]
+    Serialize(#[from] serde_json::Error),
+}
+
+async fn stale(lockfile_path: PathBuf, max_age: u64, check_remote: bool, json: bool, git_cache: Option<PathBuf>) -> Result<(), StaleError> {
+    let lockfile = Lockset::read_from_file(&lockfile_path).await?;
+    let git_cache = check_remote.then(|| git_cache.unwrap_or_else(git_cache::default_git_cache_dir));
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let report = stale::find_stale_projects(&lockfile, max_age, now, git_cache.as_deref()).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum SbomError {
+    #[error("error reading lockfile")]
+    ReadLockfile(#[from] ReadWriteLockfileError),
+
+    #[error("error generating SBOM")]
+    Generate(#[from] sbom::GenerateSbomError),
+
+    #[error("error writing `{0}`")]
+    Write(PathBuf, #[source] std::io::Error),
+}
+
+async fn generate_sbom(lockfile_path: PathBuf, format: sbom::SbomFormat, output: Option<PathBuf>) -> Result<(), SbomError> {
+    let lockfile = Lockset::read_from_file(&lockfile_path).await?;
+    let name = lockfile_path.file_stem().unwrap_or(lockfile_path.as_os_str()).to_string_lossy();
+    let document = sbom::generate_sbom(&lockfile, &name, format).await?;
+    match output {
+        Some(output) => fs::write(&output, document).await.map_err(|e| SbomError::Write(output, e))?,
+        None => println!("{document}"),
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum VerifyError {
+    #[error("error reading lockfile")]
+    ReadLockfile(#[from] ReadWriteLockfileError),
+
+    #[error("error verifying lockfile")]
+    VerifyLockfile(#[from] verify::VerifyLockfileError),
+
+    #[error("error serializing report into JSON")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("{0} projects failed verification")]
+    Failed(usize),
+}
+
+async fn verify(lockfile_path: PathBuf, check_hashes: bool, json: bool, git_cache: Option<PathBuf>) -> Result<(), VerifyError> {
+    let lockfile = Lockset::read_from_file(&lockfile_path).await?;
+    let git_cache = git_cache.unwrap_or_else(git_cache::default_git_cache_dir);
+    let report = verify::verify_lockfile(&lockfile, &git_cache, check_hashes).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+
+    if !report.passed() {
+        return Err(VerifyError::Failed(report.failed.len()));
+    }
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), MainError> {
     let args = Args::parse();
 
     match args {
-        Args::Fetch {
-            manifest_url,
-            lockfile_path,
-            revision,
-            tag,
-            lineage_device_file,
-            missing_dep_devs_file,
-            muppets,
-        } => {
-            fetch(
-                manifest_url,
-                lockfile_path,
-                revision,
-                tag,
-                lineage_device_file,
-                missing_dep_devs_file,
-                muppets,
-            )
-                .await?;
+        Args::Fetch(fetch_args) => {
+            fetch(*fetch_args).await?;
         },
 
         Args::GetLineageDevices { device_metadata_file, allow, block } => {
@@ -555,6 +1394,58 @@ async fn main() -> Result<(), MainError> {
         Args::EnsureStorePaths { lockfile_path, store_paths } => {
             ensure_store_paths(lockfile_path, store_paths).await?;
         },
+
+        Args::ImportLegacy { legacy_file, lockfile_path, disable_lfs } => {
+            import_legacy(legacy_file, lockfile_path, disable_lfs).await?;
+        },
+
+        Args::Diff { old_lockfile, new_lockfile, json } => {
+            diff(old_lockfile, new_lockfile, json).await?;
+        },
+
+        Args::Changelog { old_lockfile, new_lockfile, json, git_cache } => {
+            changelog(old_lockfile, new_lockfile, json, git_cache).await?;
+        },
+
+        Args::Update { lockfile_path, patterns, revision } => {
+            update(lockfile_path, patterns, revision).await?;
+        },
+
+        Args::Prune { lockfile_path, keep_referenced } => {
+            prune(lockfile_path, keep_referenced).await?;
+        },
+
+        Args::Outdated { lockfile_path, json } => {
+            outdated(lockfile_path, json).await?;
+        },
+
+        Args::ExportNix { lockfile_path, output } => {
+            export_nix(lockfile_path, output).await?;
+        },
+
+        Args::Query { lockfile_path, patterns, category, group, host, active, inactive, locked, unlocked, fields, json } => {
+            let filter = query::QueryFilter {
+                paths: patterns,
+                categories: category,
+                groups: group,
+                hosts: host,
+                active: (active || inactive).then_some(active),
+                locked: (locked || unlocked).then_some(locked),
+            };
+            query(lockfile_path, filter, fields, json).await?;
+        },
+
+        Args::Stale { lockfile_path, max_age, check_remote, json, git_cache } => {
+            stale(lockfile_path, max_age, check_remote, json, git_cache).await?;
+        },
+
+        Args::Sbom { lockfile_path, format, output } => {
+            generate_sbom(lockfile_path, format, output).await?;
+        },
+
+        Args::Verify { lockfile_path, check_hashes, json, git_cache } => {
+            verify(lockfile_path, check_hashes, json, git_cache).await?;
+        },
     }
 
     Ok(())
@@ -0,0 +1,98 @@
+use std::io;
+use std::path::PathBuf;
+use tokio::fs;
+use thiserror::Error;
+use repo_manifest::git_url::GitUrl;
+use crate::fetch::{
+    nix_prefetch_git,
+    NixPrefetchGitError,
+};
+use crate::lock::Lock;
+
+#[derive(Debug, Error)]
+pub enum FetchManifestRepoError {
+    #[error("a revision is required to fetch manifest repo `{0}`")]
+    MissingRevision(GitUrl),
+    #[error("error accessing local manifest repo `{0}`")]
+    IO(PathBuf, #[source] io::Error),
+    #[error("local manifest repo `{0}` is not a directory")]
+    NotADirectory(PathBuf),
+    #[error("error fetching manifest repo")]
+    Prefetch(#[from] NixPrefetchGitError),
+}
+
+/// Returns a directory containing the manifest repo, and its lock. If no `git_ref` is given,
+/// local manifest repos (absolute paths or `file://` URLs) are used as they are, including
+/// uncommitted changes, and thus aren't locked. Everything else is fetched with
+/// `nix-prefetch-git`.
+pub async fn fetch_manifest_repo(url: &GitUrl, git_ref: Option<&str>) -> Result<(PathBuf, Option<Lock>), FetchManifestRepoError> {
+    let Some(git_ref) = git_ref else {
+        let path = url
+            .to_local_path()
+            .ok_or(FetchManifestRepoError::MissingRevision(url.clone()))?;
+        let metadata = fs::metadata(&path)
+            .await
+            .map_err(|e| FetchManifestRepoError::IO(path.clone(), e))?;
+        if !metadata.is_dir() {
+            return Err(FetchManifestRepoError::NotADirectory(path));
+        }
+        return Ok((path, None));
+    };
+
+    let lock: Lock = nix_prefetch_git(url, git_ref, false, false).await?.into();
+    Ok((lock.path.clone(), Some(lock)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use repo_manifest::resolver::{
+        recursively_read_manifest_files,
+        resolve_manifest,
+    };
+    use crate::test_utils::{TempDir, file_url};
+
+    #[tokio::test]
+    async fn local_manifest_repo() {
+        let tmp = TempDir::new("manifest");
+        let manifest_dir = tmp.path().join("platform/manifest");
+        std::fs::create_dir_all(manifest_dir.join("snippets")).unwrap();
+        std::fs::write(manifest_dir.join("default.xml"), r#"<manifest>
+            <remote name="origin" fetch=".." />
+            <default remote="origin" revision="refs/heads/main" />
+            <project path="build/make" name="platform/build" />
+        </manifest>"#).unwrap();
+        std::fs::write(manifest_dir.join("pixel.xml"), r#"<manifest>
+            <include name="default.xml" />
+            <include name="snippets/pixel.xml" />
+        </manifest>"#).unwrap();
+        std::fs::write(manifest_dir.join("snippets/pixel.xml"), r#"<manifest>
+            <project path="device/google/gs101" name="device/google/gs101" />
+        </manifest>"#).unwrap();
+
+        let url = GitUrl::parse(&file_url(&manifest_dir)).unwrap();
+        let (path, lock) = fetch_manifest_repo(&url, None).await.unwrap();
+        assert_eq!(path, manifest_dir);
+        assert!(lock.is_none());
+
+        let manifest_xml = recursively_read_manifest_files(&path, Path::new("pixel.xml")).await.unwrap();
+        let manifest = resolve_manifest(&manifest_xml, &url).unwrap();
+        assert_eq!(
+            manifest.projects[Path::new("device/google/gs101")].repo_ref.repo_url.to_string(),
+            file_url(&tmp.path().join("device/google/gs101"))
+        );
+
+        let local_url = GitUrl::parse(manifest_dir.to_str().unwrap()).unwrap();
+        assert_eq!(fetch_manifest_repo(&local_url, None).await.unwrap().0, manifest_dir);
+
+        assert!(matches!(
+            fetch_manifest_repo(&GitUrl::parse(&file_url(&manifest_dir.join("default.xml"))).unwrap(), None).await,
+            Err(FetchManifestRepoError::NotADirectory(_))
+        ));
+        assert!(matches!(
+            fetch_manifest_repo(&GitUrl::parse("https://github.com/LineageOS/android").unwrap(), None).await,
+            Err(FetchManifestRepoError::MissingRevision(_))
+        ));
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use serde_json::Value;
+use repo_manifest::resolver::{
+    Category,
+    LinkCopyFile,
+};
+use crate::lock::Lockset;
+
+/// The arguments to `fetchgit` for a locked project.
+#[derive(Debug, Serialize)]
+struct NixFetchgit<'a> {
+    url: String,
+    rev: &'a str,
+    hash: &'a str,
+    #[serde(rename = "fetchLFS")]
+    fetch_lfs: bool,
+    #[serde(rename = "fetchSubmodules")]
+    fetch_submodules: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct NixProject<'a> {
+    fetchgit: NixFetchgit<'a>,
+    date: u64,
+    groups: &'a [String],
+    categories: &'a BTreeSet<Category>,
+    linkfiles: &'a [LinkCopyFile],
+    copyfiles: &'a [LinkCopyFile],
+}
+
+#[derive(Debug, Serialize)]
+struct NixLockfile<'a> {
+    #[serde(rename = "fetchCompleted")]
+    fetch_completed: bool,
+    projects: BTreeMap<&'a PathBuf, NixProject<'a>>,
+}
+
+const NIX_KEYWORDS: &[&str] = &["assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with"];
+
+fn nix_string(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{escaped}\"")
+}
+
+fn nix_attr_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_identifier = chars.next().is_some_and(|x| x.is_ascii_alphabetic() || x == '_')
+        && chars.all(|x| x.is_ascii_alphanumeric() || "_'-".contains(x))
+        && !NIX_KEYWORDS.contains(&name);
+    if is_identifier {
+        name.to_string()
+    } else {
+        nix_string(name)
+    }
+}
+
+/// Renders a JSON value as the equivalent Nix expression.
+fn to_nix(value: &Value, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent + 1);
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(x) => out.push_str(&x.to_string()),
+        Value::Number(x) => out.push_str(&x.to_string()),
+        Value::String(x) => out.push_str(&nix_string(x)),
+        Value::Array(items) if items.is_empty() => out.push_str("[ ]"),
+        Value::Array(items) => {
+            out.push_str("[\n");
+            for item in items {
+                out.push_str(&pad);
+                to_nix(item, indent + 1, out);
+                out.push('\n');
+            }
+            out.push_str(&"  ".repeat(indent));
+            out.push(']');
+        },
+        Value::Object(attrs) if attrs.is_empty() => out.push_str("{ }"),
+        Value::Object(attrs) => {
+            out.push_str("{\n");
+            for (name, value) in attrs {
+                out.push_str(&pad);
+                out.push_str(&nix_attr_name(name));
+                out.push_str(" = ");
+                to_nix(value, indent + 1, out);
+                out.push_str(";\n");
+            }
+            out.push_str(&"  ".repeat(indent));
+            out.push('}');
+        },
+    }
+}
+
+/// Renders the active, locked projects of a lockfile as a Nix expression: An attrset of the
+/// `fetchgit` arguments, date, groups, categories and linkfiles/copyfiles of every project, by
+/// path. Unlike the JSON lockfile, this doesn't need to be parsed at evaluation time, and
+/// doesn't depend on robotnix to be used.
+pub fn export_nix(lockset: &Lockset, lockfile_path: &Path) -> String {
+    let mut projects = BTreeMap::new();
+    for (path, entry) in lockset.entries.iter().filter(|(_, x)| x.project.active) {
+        let Some(lock) = &entry.lock else {
+            continue;
+        };
+        let project = &entry.project;
+        projects.insert(path, NixProject {
+            fetchgit: NixFetchgit {
+                url: project.repo_ref.repo_url.to_string(),
+                rev: &lock.commit,
+                hash: &lock.nix_hash,
+                fetch_lfs: project.repo_ref.fetch_lfs,
+                fetch_submodules: project.repo_ref.fetch_submodules,
+            },
+            date: lock.date,
+            groups: &project.groups,
+            categories: &project.categories,
+            linkfiles: &project.linkfiles,
+            copyfiles: &project.copyfiles,
+        });
+    }
+
+    let lockfile = NixLockfile {
+        fetch_completed: projects.len() == lockset.entries.values().filter(|x| x.project.active).count(),
+        projects,
+    };
+    let value = serde_json::to_value(&lockfile).expect("lockfile can be represented as JSON");
+
+    let mut out = format!(
+        "# Generated by `repo-tool export-nix` from `{}`. Do not edit.\n",
+        lockfile_path.file_name().unwrap_or(lockfile_path.as_os_str()).to_string_lossy(),
+    );
+    to_nix(&value, 0, &mut out);
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::lock::Lock;
+    use crate::test_utils::test_project;
+
+    #[test]
+    fn export_lockfile() {
+        let projects = HashMap::from(["build/make", "art", "external/unlocked"].map(|path| {
+            (PathBuf::from(path), test_project(path, "https://example.com/platform/build", "refs/heads/main"))
+        }));
+        let mut lockset = Lockset::new(&projects, Path::new("test.lock"));
+        let entry = lockset.entries.get_mut(Path::new("build/make")).unwrap();
+        entry.project.groups = vec!["pdk".to_string(), "${x}\"".to_string()];
+        entry.project.categories.insert(Category::DeviceSpecific("oriole".to_string()));
+        entry.lock = Some(Lock {
+            commit: "a".repeat(40),
+            nix_hash: "sha256-AAAA".to_string(),
+            path: PathBuf::new(),
+            date: 1700000000,
+        });
+        lockset.entries.get_mut(Path::new("art")).unwrap().project.active = false;
+
+        assert_eq!(export_nix(&lockset, Path::new("/tmp/test.lock")), format!(r#"# Generated by `repo-tool export-nix` from `test.lock`. Do not edit.
+{{
+  fetchCompleted = false;
+  projects = {{
+    "build/make" = {{
+      categories = [
+        "Default"
+        {{
+          DeviceSpecific = "oriole";
+        }}
+      ];
+      copyfiles = [ ];
+      date = 1700000000;
+      fetchgit = {{
+        fetchLFS = true;
+        fetchSubmodules = false;
+        hash = "sha256-AAAA";
+        rev = "{}";
+        url = "https://example.com/platform/build";
+      }};
+      groups = [
+        "pdk"
+        "\${{x}}\""
+      ];
+      linkfiles = [ ];
+    }};
+  }};
+}}
+"#, "a".repeat(40)));
+    }
+}
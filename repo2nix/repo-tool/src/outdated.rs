@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::PathBuf;
+use serde::Serialize;
+use thiserror::Error;
+use crate::diff::Change;
+use crate::fetch::{
+    git_ls_remote,
+    GitLsRemoteError,
+};
+use crate::lock::{
+    is_commit_id,
+    Lockset,
+};
+
+#[derive(Debug, Default, Serialize)]
+pub struct OutdatedReport {
+    /// The locked and current commit of the manifest repo, if its ref moved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<Change<String>>,
+    /// The locked (if any) and current commit of every project whose ref moved.
+    pub projects: BTreeMap<PathBuf, Change<Option<String>>>,
+    /// Projects whose ref doesn't exist anymore.
+    pub missing: BTreeSet<PathBuf>,
+}
+
+impl OutdatedReport {
+    pub fn is_empty(&self) -> bool {
+        self.manifest.is_none() && self.projects.is_empty() && self.missing.is_empty()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CheckOutdatedError {
+    #[error("error resolving ref of manifest repo")]
+    Manifest(#[source] GitLsRemoteError),
+    #[error("error resolving ref of project `{0}`")]
+    Project(PathBuf, #[source] GitLsRemoteError),
+}
+
+/// Resolves the refs of the manifest repo and of all active projects with `git ls-remote` and
+/// compares them to the locked commits, without prefetching anything.
+pub async fn check_outdated(lockset: &Lockset) -> Result<OutdatedReport, CheckOutdatedError> {
+    let mut report = OutdatedReport::default();
+
+    if let Some(manifest) = &lockset.manifest {
+        if let (Some(git_ref), Some(lock)) = (&manifest.git_ref, &manifest.lock) {
+            eprintln!("Checking manifest repo `{}`...", manifest.url);
+            let current = git_ls_remote(&manifest.url.to_string(), git_ref)
+                .await
+                .map_err(CheckOutdatedError::Manifest)?;
+            if current != lock.commit {
+                report.manifest = Some(Change { old: lock.commit.clone(), new: current });
+            }
+        }
+    }
+
+    for (path, entry) in lockset.entries.iter().filter(|(_, x)| x.project.active) {
+        let repo_ref = &entry.project.repo_ref;
+        let current = if is_commit_id(&repo_ref.revision) {
+            repo_ref.revision.clone()
+        } else {
+            eprintln!("Checking `{}`...", path.display());
+            match git_ls_remote(&repo_ref.repo_url.to_string(), &repo_ref.revision).await {
+                Ok(commit) => commit,
+                Err(GitLsRemoteError::RevNotFound) => {
+                    report.missing.insert(path.clone());
+                    continue;
+                },
+                Err(e) => return Err(CheckOutdatedError::Project(path.clone(), e)),
+            }
+        };
+
+        let locked = entry.lock.as_ref().map(|x| x.commit.clone());
+        if locked.as_ref() != Some(&current) {
+            report.projects.insert(path.clone(), Change { old: locked, new: Some(current) });
+        }
+    }
+
+    Ok(report)
+}
+
+impl fmt::Display for OutdatedReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "Everything is up to date.");
+        }
+
+        if let Some(Change { old, new }) = &self.manifest {
+            writeln!(f, "Manifest repo: {old} -> {new}")?;
+        }
+        if !self.projects.is_empty() {
+            writeln!(f, "Outdated projects:")?;
+            for (path, Change { old, new }) in self.projects.iter() {
+                writeln!(
+                    f,
+                    "  {}: {} -> {}",
+                    path.display(),
+                    old.as_deref().unwrap_or("(unlocked)"),
+                    new.as_deref().unwrap_or("(unlocked)"),
+                )?;
+            }
+        }
+        if !self.missing.is_empty() {
+            writeln!(f, "Projects whose revision doesn't exist anymore:")?;
+            for path in self.missing.iter() {
+                writeln!(f, "  {}", path.display())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use crate::lock::Lock;
+    use crate::test_utils::{test_project, TempDir, git, init_repo, file_url};
+
+    #[tokio::test]
+    async fn report_moved_refs() {
+        let tmp = TempDir::new("outdated");
+        let old_commit = init_repo(&tmp.path().join("build"));
+        git(&tmp.path().join("build"), &["commit", "--quiet", "--allow-empty", "-m", "Update"]);
+        let new_commit = git(&tmp.path().join("build"), &["rev-parse", "HEAD"]);
+        let art_commit = init_repo(&tmp.path().join("art"));
+
+        let projects = HashMap::from([
+            ("build", "refs/heads/main"),
+            ("art", "refs/heads/main"),
+            ("gone", "refs/heads/gone"),
+        ].map(|(path, revision)| {
+            let url = file_url(&tmp.path().join(if path == "gone" { "art" } else { path }));
+            (PathBuf::from(path), test_project(path, &url, revision))
+        }));
+        let mut lockset = Lockset::new(&projects, &tmp.path().join("test.lock"));
+        for (path, commit) in [("build", &old_commit), ("art", &art_commit)] {
+            lockset.entries.get_mut(Path::new(path)).unwrap().lock = Some(Lock {
+                commit: commit.clone(),
+                nix_hash: String::new(),
+                path: PathBuf::new(),
+                date: 0,
+            });
+        }
+
+        let report = check_outdated(&lockset).await.unwrap();
+        assert_eq!(report.projects.len(), 1);
+        assert_eq!(report.projects[Path::new("build")], Change { old: Some(old_commit), new: Some(new_commit) });
+        assert_eq!(report.missing, BTreeSet::from([PathBuf::from("gone")]));
+        assert!(report.manifest.is_none());
+        assert!(!tmp.path().join("test.lock").exists());
+    }
+}
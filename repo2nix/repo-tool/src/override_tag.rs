@@ -0,0 +1,137 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use thiserror::Error;
+use repo_manifest::resolver::Manifest;
+use crate::fetch::{
+    git_ls_remote,
+    GitLsRemoteError,
+};
+
+#[derive(Debug, Error)]
+pub enum OverrideTagError {
+    #[error("unknown remote `{0}`")]
+    UnknownRemote(String),
+    #[error("error checking whether project `{0}` has the override tag")]
+    GitLsRemote(PathBuf, #[source] GitLsRemoteError),
+}
+
+/// Whether `repo_url` belongs to the remote at `remote_url`, i.e. `remote_url` is a prefix of it
+/// that ends at a path segment boundary.
+fn is_remote_repo(repo_url: &str, remote_url: &str) -> bool {
+    if remote_url.ends_with('/') || remote_url.ends_with(':') {
+        return repo_url.starts_with(remote_url);
+    }
+    repo_url
+        .strip_prefix(remote_url)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Sets the revision of all projects (or, if `remotes` isn't empty, of the projects from these
+/// remotes) to `refs/tags/<tag>`. Projects that don't have that tag keep their manifest revision
+/// and are returned.
+pub async fn apply_override_tag(manifest: &mut Manifest, tag: &str, remotes: &[String]) -> Result<BTreeSet<PathBuf>, OverrideTagError> {
+    let remote_urls = remotes
+        .iter()
+        .map(|name| manifest
+            .remotes
+            .get(name)
+            .map(|x| x.url.to_string())
+            .ok_or(OverrideTagError::UnknownRemote(name.clone()))
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let git_ref = format!("refs/tags/{tag}");
+    let mut paths: Vec<_> = manifest.projects.keys().cloned().collect();
+    paths.sort();
+
+    let mut fallback_projects = BTreeSet::new();
+    for path in paths {
+        let project = manifest.projects.get_mut(&path).unwrap();
+        let repo_url = project.repo_ref.repo_url.to_string();
+        if !remote_urls.is_empty() && !remote_urls.iter().any(|x| is_remote_repo(&repo_url, x)) {
+            continue;
+        }
+
+        eprintln!("Checking that `{repo_url}` has tag `{tag}`...");
+        match git_ls_remote(&repo_url, &git_ref).await {
+            Ok(_) => project.repo_ref.revision = git_ref.clone(),
+            Err(GitLsRemoteError::RevNotFound) => {
+                eprintln!("Not found, falling back to `{}`.", project.repo_ref.revision);
+                fallback_projects.insert(path);
+            },
+            Err(e) => return Err(OverrideTagError::GitLsRemote(path, e)),
+        }
+    }
+
+    Ok(fallback_projects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use repo_manifest::git_url::GitUrl;
+    use repo_manifest::resolver::Remote;
+    use crate::fetch::git_ls_remote;
+    use crate::test_utils::{test_project, TempDir, git, init_repo, file_url};
+
+    #[test]
+    fn remote_matches_whole_path_segments() {
+        assert!(is_remote_repo("https://host/a/build", "https://host/a"));
+        assert!(is_remote_repo("https://host/a/build", "https://host/a/"));
+        assert!(is_remote_repo("https://host/a", "https://host/a"));
+        assert!(!is_remote_repo("https://host/ab/build", "https://host/a"));
+        assert!(is_remote_repo("git@host:vendor/build", "git@host:"));
+        assert!(!is_remote_repo("git@hostb:vendor/build", "git@host"));
+    }
+
+    #[tokio::test]
+    async fn override_tag_with_fallback() {
+        let tmp = TempDir::new("override-tag");
+        let tagged_commit = init_repo(&tmp.path().join("remote/tagged"));
+        git(&tmp.path().join("remote/tagged"), &["tag", "-a", "-m", "Release", "android-1.0"]);
+        init_repo(&tmp.path().join("remote/untagged"));
+        init_repo(&tmp.path().join("other/tagged"));
+        git(&tmp.path().join("other/tagged"), &["tag", "android-1.0"]);
+
+        let remote_url = GitUrl::parse(&format!("{}/", file_url(&tmp.path().join("remote")))).unwrap();
+        let mut projects = HashMap::new();
+        for (path, url) in [
+            ("tagged", file_url(&tmp.path().join("remote/tagged"))),
+            ("untagged", file_url(&tmp.path().join("remote/untagged"))),
+            ("other", file_url(&tmp.path().join("other/tagged"))),
+        ] {
+            projects.insert(PathBuf::from(path), test_project(path, &url, "refs/heads/main"));
+        }
+        let mut manifest = Manifest {
+            base_url: remote_url.to_string(),
+            remotes: HashMap::from([("remote".to_string(), Remote {
+                name: "remote".to_string(),
+                url: remote_url,
+                revision: None,
+            })]),
+            default_remote: None,
+            projects,
+        };
+
+        let fallbacks = apply_override_tag(&mut manifest, "android-1.0", &["remote".to_string()]).await.unwrap();
+        assert_eq!(fallbacks, BTreeSet::from([PathBuf::from("untagged")]));
+        assert_eq!(manifest.projects[Path::new("tagged")].repo_ref.revision, "refs/tags/android-1.0");
+        assert_eq!(manifest.projects[Path::new("untagged")].repo_ref.revision, "refs/heads/main");
+        // Filtered out by remote
+        assert_eq!(manifest.projects[Path::new("other")].repo_ref.revision, "refs/heads/main");
+
+        // Annotated tags must resolve to the tagged commit, not to the tag object.
+        let resolved = git_ls_remote(
+            &file_url(&tmp.path().join("remote/tagged")),
+            "refs/tags/android-1.0"
+        ).await.unwrap();
+        assert_eq!(resolved, tagged_commit);
+
+        assert!(matches!(
+            apply_override_tag(&mut manifest, "android-1.0", &["missing".to_string()]).await,
+            Err(OverrideTagError::UnknownRemote(_))
+        ));
+    }
+}
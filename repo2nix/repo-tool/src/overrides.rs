@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use repo_manifest::git_url::GitUrl;
+use repo_manifest::resolver::{
+    GitRepoRef,
+    Project,
+};
+use crate::lock::is_commit_id;
+
+/// Local changes to a project on top of what the manifest says, e.g. to hold it at an older
+/// commit or to point it at a fork. Unset fields are taken from the manifest.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectOverride {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<GitUrl>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    /// Pins the project to this exact commit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetch_lfs: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetch_submodules: Option<bool>,
+    /// Why the project is overridden, for humans reading the lockfile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// An override as recorded in a lockfile entry, along with what the manifest said.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppliedOverride {
+    #[serde(rename = "override")]
+    pub project_override: ProjectOverride,
+    pub upstream: GitRepoRef,
+}
+
+#[derive(Debug, Error)]
+pub enum ReadOverridesError {
+    #[error("error reading overrides file")]
+    IO(#[from] io::Error),
+    #[error("error parsing overrides file")]
+    Parse(#[from] serde_json::Error),
+    #[error("invalid commit `{1}` in override of `{0}`")]
+    InvalidCommit(PathBuf, String),
+    #[error("override of `{0}` sets both a revision and a commit")]
+    RevisionAndCommit(PathBuf),
+}
+
+/// Reads an overrides file, i.e. a JSON object mapping project paths to overrides.
+pub async fn read_overrides(path: &Path) -> Result<BTreeMap<PathBuf, ProjectOverride>, ReadOverridesError> {
+    let overrides: BTreeMap<PathBuf, ProjectOverride> = serde_json::from_slice(&fs::read(path).await?)?;
+    for (path, project_override) in overrides.iter() {
+        if project_override.revision.is_some() && project_override.commit.is_some() {
+            return Err(ReadOverridesError::RevisionAndCommit(path.clone()));
+        }
+        if let Some(commit) = &project_override.commit {
+            if !is_commit_id(commit) {
+                return Err(ReadOverridesError::InvalidCommit(path.clone(), commit.clone()));
+            }
+        }
+    }
+    Ok(overrides)
+}
+
+/// Applies the overrides to the projects. Returns the applied overrides by path, and the paths
+/// of overrides that don't match any project.
+pub fn apply_overrides(projects: &mut HashMap<PathBuf, Project>, overrides: &BTreeMap<PathBuf, ProjectOverride>) -> (BTreeMap<PathBuf, AppliedOverride>, BTreeSet<PathBuf>) {
+    let mut applied = BTreeMap::new();
+    let mut unused = BTreeSet::new();
+
+    for (path, project_override) in overrides.iter() {
+        let Some(project) = projects.get_mut(path) else {
+            unused.insert(path.clone());
+            continue;
+        };
+
+        let upstream = project.repo_ref.clone();
+        let repo_ref = &mut project.repo_ref;
+        if let Some(url) = &project_override.url {
+            repo_ref.repo_url = url.clone();
+        }
+        if let Some(revision) = project_override.commit.as_ref().or(project_override.revision.as_ref()) {
+            repo_ref.revision = revision.clone();
+        }
+        if let Some(fetch_lfs) = project_override.fetch_lfs {
+            repo_ref.fetch_lfs = fetch_lfs;
+        }
+        if let Some(fetch_submodules) = project_override.fetch_submodules {
+            repo_ref.fetch_submodules = fetch_submodules;
+        }
+
+        applied.insert(path.clone(), AppliedOverride {
+            project_override: project_override.clone(),
+            upstream,
+        });
+    }
+
+    (applied, unused)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_project;
+
+    #[test]
+    fn override_projects() {
+        let overrides: BTreeMap<PathBuf, ProjectOverride> = serde_json::from_str(r#"{
+            "external/libwebp": {
+                "commit": "0123456789abcdef0123456789abcdef01234567",
+                "reason": "upstream regression"
+            },
+            "frameworks/base": {
+                "url": "git@github.com:example/platform_frameworks_base",
+                "revision": "refs/heads/fork",
+                "fetch_lfs": false
+            },
+            "removed": { "revision": "refs/heads/main" }
+        }"#).unwrap();
+
+        let mut projects = HashMap::new();
+        for path in ["external/libwebp", "frameworks/base", "art"] {
+            projects.insert(PathBuf::from(path), test_project(path, "https://android.googlesource.com/platform/x", "refs/tags/android-15.0.0_r32"));
+        }
+
+        let (applied, unused) = apply_overrides(&mut projects, &overrides);
+        assert_eq!(unused, BTreeSet::from([PathBuf::from("removed")]));
+        assert_eq!(applied.keys().collect::<Vec<_>>(), [Path::new("external/libwebp"), Path::new("frameworks/base")]);
+        assert_eq!(applied[Path::new("external/libwebp")].upstream.revision, "refs/tags/android-15.0.0_r32");
+
+        let libwebp = &projects[Path::new("external/libwebp")].repo_ref;
+        assert_eq!(libwebp.revision, "0123456789abcdef0123456789abcdef01234567");
+        let base = &projects[Path::new("frameworks/base")].repo_ref;
+        assert_eq!(base.repo_url.to_string(), "git@github.com:example/platform_frameworks_base");
+        assert_eq!(base.revision, "refs/heads/fork");
+        assert!(!base.fetch_lfs);
+        assert_eq!(projects[Path::new("art")].repo_ref.revision, "refs/tags/android-15.0.0_r32");
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use crate::cache::{
+    find_lockfiles,
+    read_lockfile_if_any,
+    BuildLockCacheError,
+    LockCacheKey,
+};
+use crate::lock::Lockset;
+
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    pub removed: BTreeSet<PathBuf>,
+    /// Inactive entries that were kept because another lockfile references their lock.
+    pub kept: BTreeSet<PathBuf>,
+}
+
+/// Collects the locks of the active projects of all lockfiles found in `search_paths`, except for
+/// the lockfile at `own_path`.
+pub async fn referenced_locks(search_paths: &[PathBuf], own_path: &Path) -> Result<HashSet<LockCacheKey>, BuildLockCacheError> {
+    let own_path = fs::canonicalize(own_path).await.unwrap_or(own_path.to_path_buf());
+    let mut referenced = HashSet::new();
+    for search_path in search_paths {
+        for lockfile_path in find_lockfiles(search_path).await? {
+            if fs::canonicalize(&lockfile_path).await.unwrap_or(lockfile_path.clone()) == own_path {
+                continue;
+            }
+            let Some(lockset) = read_lockfile_if_any(&lockfile_path).await? else {
+                continue;
+            };
+            for entry in lockset.entries.values().filter(|x| x.project.active) {
+                if let Some(lock) = &entry.lock {
+                    referenced.insert(LockCacheKey::new(&entry.project.repo_ref, &lock.commit));
+                }
+            }
+        }
+    }
+    Ok(referenced)
+}
+
+/// Removes all inactive entries from the lockset, except for those whose lock is in `referenced`.
+pub fn prune_inactive(lockset: &mut Lockset, referenced: &HashSet<LockCacheKey>) -> PruneReport {
+    let mut report = PruneReport::default();
+    for (path, entry) in lockset.entries.iter().filter(|(_, x)| !x.project.active) {
+        let is_referenced = entry
+            .lock
+            .as_ref()
+            .is_some_and(|lock| referenced.contains(&LockCacheKey::new(&entry.project.repo_ref, &lock.commit)));
+        if is_referenced {
+            report.kept.insert(path.clone());
+        } else {
+            report.removed.insert(path.clone());
+        }
+    }
+
+    for path in report.removed.iter() {
+        lockset.entries.remove(path);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::lock::Lock;
+    use crate::test_utils::{test_project, TempDir};
+
+    #[tokio::test]
+    async fn prune_unreferenced_entries() {
+        let tmp = TempDir::new("prune");
+        let projects = HashMap::from(["build/make", "old/referenced", "old/unreferenced", "old/unlocked"].map(|path| {
+            (PathBuf::from(path), test_project(path, &format!("https://example.com/{path}"), "refs/heads/main"))
+        }));
+        let mut lockset = Lockset::new(&projects, &tmp.path().join("own.lock"));
+        for (path, entry) in lockset.entries.iter_mut() {
+            if path != Path::new("old/unlocked") {
+                entry.lock = Some(Lock {
+                    commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+                    nix_hash: String::new(),
+                    path: PathBuf::new(),
+                    date: 0,
+                });
+            }
+        }
+
+        let mut other = Lockset::new(&HashMap::new(), &tmp.path().join("other.lock"));
+        other.entries.insert(PathBuf::from("old/referenced"), lockset.entries[Path::new("old/referenced")].clone());
+        other.write(true).await.unwrap();
+
+        lockset.deactivate_all();
+        lockset.entries.get_mut(Path::new("build/make")).unwrap().project.active = true;
+        // The lockset's own (now inactive) entries don't count as references.
+        lockset.write(true).await.unwrap();
+
+        let referenced = referenced_locks(&[tmp.path().to_path_buf()], &lockset.path).await.unwrap();
+        let report = prune_inactive(&mut lockset, &referenced);
+        assert_eq!(report.kept, BTreeSet::from([PathBuf::from("old/referenced")]));
+        assert_eq!(report.removed, BTreeSet::from([PathBuf::from("old/unlocked"), PathBuf::from("old/unreferenced")]));
+        assert_eq!(lockset.entries.keys().collect::<Vec<_>>(), [Path::new("build/make"), Path::new("old/referenced")]);
+    }
+}
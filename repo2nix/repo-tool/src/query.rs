@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use clap::ValueEnum;
+use serde::Serialize;
+use repo_manifest::resolver::Category;
+use crate::lock::{
+    Lockset,
+    LocksetEntry,
+};
+use crate::utils::{
+    format_category,
+    path_matches_glob,
+};
+
+/// Selects lockfile entries. Within each filter, any of the given values may match; entries
+/// need to match every filter that is set.
+#[derive(Debug, Default)]
+pub struct QueryFilter {
+    /// Path glob patterns, see `path_matches_glob`.
+    pub paths: Vec<String>,
+    pub categories: Vec<Category>,
+    pub groups: Vec<String>,
+    pub hosts: Vec<String>,
+    pub active: Option<bool>,
+    pub locked: Option<bool>,
+}
+
+impl QueryFilter {
+    pub fn matches(&self, path: &Path, entry: &LocksetEntry) -> bool {
+        let project = &entry.project;
+        let host = project.repo_ref.repo_url.host();
+        (self.paths.is_empty() || self.paths.iter().any(|x| path_matches_glob(path, x)))
+            && (self.categories.is_empty() || self.categories.iter().any(|x| project.categories.contains(x)))
+            && (self.groups.is_empty() || self.groups.iter().any(|x| project.groups.contains(x)))
+            && (self.hosts.is_empty() || self.hosts.iter().any(|x| host == Some(x.as_str())))
+            && self.active.is_none_or(|x| project.active == x)
+            && self.locked.is_none_or(|x| entry.lock.is_some() == x)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryField {
+    Path,
+    StorePath,
+    Commit,
+    Url,
+    Revision,
+    Hash,
+    Date,
+    Categories,
+    Groups,
+}
+
+impl QueryField {
+    /// The value of the field for an entry, or `None` for lock fields of unlocked entries.
+    fn value(&self, path: &Path, entry: &LocksetEntry) -> Option<String> {
+        let project = &entry.project;
+        let lock = entry.lock.as_ref();
+        match self {
+            QueryField::Path => Some(path.to_string_lossy().to_string()),
+            QueryField::StorePath => lock.map(|x| x.path.to_string_lossy().to_string()),
+            QueryField::Commit => lock.map(|x| x.commit.clone()),
+            QueryField::Url => Some(project.repo_ref.repo_url.to_string()),
+            QueryField::Revision => Some(project.repo_ref.revision.clone()),
+            QueryField::Hash => lock.map(|x| x.nix_hash.clone()),
+            QueryField::Date => lock.map(|x| x.date.to_string()),
+            QueryField::Categories => Some(project.categories.iter().map(format_category).collect::<Vec<_>>().join(",")),
+            QueryField::Groups => Some(project.groups.join(",")),
+        }
+    }
+}
+
+/// Returns the selected fields of all entries matching `filter`, in path order.
+pub fn query(lockset: &Lockset, filter: &QueryFilter, fields: &[QueryField]) -> Vec<BTreeMap<QueryField, Option<String>>> {
+    lockset.entries
+        .iter()
+        .filter(|(path, entry)| filter.matches(path, entry))
+        .map(|(path, entry)| fields.iter().map(|x| (*x, x.value(path, entry))).collect())
+        .collect()
+}
+
+/// Renders query results as one line per entry, with the fields separated by tabs in the order
+/// they were requested. Missing values are rendered as `-`.
+pub fn render_text(results: &[BTreeMap<QueryField, Option<String>>], fields: &[QueryField]) -> String {
+    let mut text = String::new();
+    for result in results {
+        let values: Vec<_> = fields
+            .iter()
+            .map(|x| result[x].as_deref().unwrap_or("-"))
+            .collect();
+        text += &values.join("\t");
+        text += "\n";
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use crate::lock::Lock;
+    use crate::test_utils::test_project;
+
+    #[test]
+    fn query_entries() {
+        let projects = HashMap::from([
+            ("vendor/adevtool", "https://github.com/GrapheneOS/adevtool"),
+            ("device/google/oriole", "https://github.com/GrapheneOS/device_google_oriole"),
+            ("build/make", "https://android.googlesource.com/platform/build"),
+        ].map(|(path, url)| (PathBuf::from(path), test_project(path, url, "refs/heads/main"))));
+        let mut lockset = Lockset::new(&projects, Path::new("test.lock"));
+        lockset.entries.get_mut(Path::new("vendor/adevtool")).unwrap().lock = Some(Lock {
+            commit: "a".repeat(40),
+            nix_hash: "sha256-AAAA".to_string(),
+            path: PathBuf::from("/nix/store/aaaa-adevtool"),
+            date: 0,
+        });
+        let oriole = lockset.entries.get_mut(Path::new("device/google/oriole")).unwrap();
+        oriole.project.categories = [Category::DeviceSpecific("oriole".to_string())].into();
+        oriole.project.groups = vec!["device".to_string()];
+
+        let fields = [QueryField::Path, QueryField::StorePath];
+        let filter = QueryFilter { paths: vec!["vendor/adevtool".to_string()], ..Default::default() };
+        assert_eq!(render_text(&query(&lockset, &filter, &fields), &fields), "vendor/adevtool\t/nix/store/aaaa-adevtool\n");
+
+        let filter = QueryFilter { hosts: vec!["github.com".to_string()], locked: Some(false), ..Default::default() };
+        assert_eq!(render_text(&query(&lockset, &filter, &fields), &fields), "device/google/oriole\t-\n");
+
+        let filter = QueryFilter { categories: vec![Category::Default], groups: vec!["device".to_string()], ..Default::default() };
+        assert!(query(&lockset, &filter, &fields).is_empty());
+        let filter = QueryFilter { categories: vec![Category::Default], active: Some(true), ..Default::default() };
+        assert_eq!(query(&lockset, &filter, &fields).len(), 2);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use thiserror::Error;
+use repo_manifest::resolver::Project;
+use crate::lock::is_commit_id;
+
+#[derive(Debug, Error)]
+pub enum ReadRepoPropError {
+    #[error("error reading repo.prop file")]
+    IO(#[from] io::Error),
+    #[error("repo.prop file contains invalid UTF-8")]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error("invalid line in repo.prop file: `{0}`")]
+    InvalidLine(String),
+    #[error("project `{0}` is listed multiple times in repo.prop file")]
+    DuplicateProject(String),
+}
+
+/// Parses the `<project name> <commit>` lines of a `repo.prop` file as shipped with factory
+/// images.
+pub fn parse_repo_prop(text: &str) -> Result<BTreeMap<String, String>, ReadRepoPropError> {
+    let mut revisions = BTreeMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [name, commit] if is_commit_id(commit) => {
+                if revisions.insert(name.to_string(), commit.to_string()).is_some() {
+                    return Err(ReadRepoPropError::DuplicateProject(name.to_string()));
+                }
+            },
+            _ => return Err(ReadRepoPropError::InvalidLine(line.to_string())),
+        }
+    }
+
+    Ok(revisions)
+}
+
+pub async fn read_repo_prop(path: &Path) -> Result<BTreeMap<String, String>, ReadRepoPropError> {
+    let bytes = fs::read(path).await?;
+    parse_repo_prop(std::str::from_utf8(&bytes)?)
+}
+
+fn project_matches(project: &Project, name: &str) -> bool {
+    let url_path = project.repo_ref.repo_url.path().trim_end_matches('/');
+    let url_path = url_path.strip_suffix(".git").unwrap_or(url_path);
+    url_path
+        .strip_suffix(name)
+        .is_some_and(|x| x.is_empty() || x.ends_with('/'))
+}
+
+#[derive(Debug, Default)]
+pub struct RepoPropReport {
+    /// Projects that have no entry in the repo.prop file, and thus keep their manifest revision.
+    pub missing_projects: BTreeSet<PathBuf>,
+    /// repo.prop entries that don't correspond to any project in the manifest.
+    pub unused_entries: BTreeSet<String>,
+}
+
+/// Pins the revision of every project listed in `revisions` to the commit given there. As
+/// repo.prop files refer to projects by name and not by path, projects are matched by the suffix
+/// of their repo URL, like `mk_repo_file.py --repo-prop` did.
+pub fn apply_repo_prop(projects: &mut HashMap<PathBuf, Project>, revisions: &BTreeMap<String, String>) -> RepoPropReport {
+    let mut report = RepoPropReport::default();
+    let mut used_entries = BTreeSet::new();
+
+    for (path, project) in projects.iter_mut() {
+        let entry = revisions
+            .iter()
+            .filter(|(name, _)| project_matches(project, name))
+            .max_by_key(|(name, _)| name.len());
+        match entry {
+            Some((name, commit)) => {
+                project.repo_ref.revision = commit.clone();
+                used_entries.insert(name.clone());
+            },
+            None => {
+                report.missing_projects.insert(path.clone());
+            },
+        }
+    }
+
+    report.unused_entries = revisions
+        .keys()
+        .filter(|x| !used_entries.contains(*x))
+        .cloned()
+        .collect();
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_project;
+
+    #[test]
+    fn pin_revisions() {
+        let revisions = parse_repo_prop(
+            "platform/build 0123456789abcdef0123456789abcdef01234567\n\
+             platform/build/soong 89abcdef0123456789abcdef0123456789abcdef\n\
+             platform/removed 0000000000000000000000000000000000000000\n"
+        ).unwrap();
+
+        let mut projects = HashMap::new();
+        for (path, url) in [
+            ("build/make", "https://android.googlesource.com/platform/build"),
+            ("build/soong", "https://android.googlesource.com/platform/build/soong.git"),
+            ("art", "https://android.googlesource.com/platform/art"),
+        ] {
+            projects.insert(PathBuf::from(path), test_project(path, url, "refs/heads/main"));
+        }
+
+        let report = apply_repo_prop(&mut projects, &revisions);
+        assert_eq!(projects[Path::new("build/make")].repo_ref.revision, "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(projects[Path::new("build/soong")].repo_ref.revision, "89abcdef0123456789abcdef0123456789abcdef");
+        assert_eq!(projects[Path::new("art")].repo_ref.revision, "refs/heads/main");
+        assert_eq!(report.missing_projects, BTreeSet::from([PathBuf::from("art")]));
+        assert_eq!(report.unused_entries, BTreeSet::from(["platform/removed".to_string()]));
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        assert!(parse_repo_prop("platform/build refs/heads/main\n").is_err());
+        assert!(parse_repo_prop("platform/build\n").is_err());
+    }
+}
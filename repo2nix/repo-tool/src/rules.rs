@@ -0,0 +1,134 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use repo_manifest::resolver::{
+    Manifest,
+    Project,
+};
+use crate::utils::path_matches_glob;
+
+/// Fetch options for all projects whose path matches the `path` glob pattern. Options that are
+/// unset are left as they are.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectRule {
+    pub path: String,
+    #[serde(default)]
+    pub fetch_lfs: Option<bool>,
+    #[serde(default)]
+    pub fetch_submodules: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FetchRules {
+    /// If non-empty, only projects matching one of these patterns are locked.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Projects matching one of these patterns are never locked.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Per-project fetch options. Later rules take precedence over earlier ones.
+    #[serde(default)]
+    pub projects: Vec<ProjectRule>,
+}
+
+#[derive(Debug, Error)]
+pub enum ReadFetchRulesError {
+    #[error("error reading fetch rules file")]
+    IO(#[from] io::Error),
+    #[error("error parsing fetch rules file")]
+    Parse(#[from] serde_json::Error),
+}
+
+impl FetchRules {
+    pub async fn read_from_file(path: &Path) -> Result<Self, ReadFetchRulesError> {
+        Ok(serde_json::from_slice(&fs::read(path).await?)?)
+    }
+
+    pub fn is_included(&self, path: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|x| path_matches_glob(path, x))) &&
+            !self.exclude.iter().any(|x| path_matches_glob(path, x))
+    }
+
+    /// Whether some rule explicitly sets `fetch_lfs` for the given path.
+    pub fn sets_fetch_lfs(&self, path: &Path) -> bool {
+        self.projects.iter().any(|x| x.fetch_lfs.is_some() && path_matches_glob(path, &x.path))
+    }
+
+    /// Whether some rule explicitly sets `fetch_submodules` for the given path.
+    pub fn sets_fetch_submodules(&self, path: &Path) -> bool {
+        self.projects.iter().any(|x| x.fetch_submodules.is_some() && path_matches_glob(path, &x.path))
+    }
+
+    pub fn apply_fetch_options(&self, project: &mut Project) {
+        for rule in self.projects.iter().filter(|x| path_matches_glob(&project.path, &x.path)) {
+            if let Some(fetch_lfs) = rule.fetch_lfs {
+                project.repo_ref.fetch_lfs = fetch_lfs;
+            }
+            if let Some(fetch_submodules) = rule.fetch_submodules {
+                project.repo_ref.fetch_submodules = fetch_submodules;
+            }
+        }
+    }
+
+    /// Removes all projects that aren't included by the rules from the manifest, sets the fetch
+    /// options of the remaining ones, and returns the paths of the removed projects.
+    pub fn apply(&self, manifest: &mut Manifest) -> BTreeSet<PathBuf> {
+        let excluded: BTreeSet<_> = manifest
+            .projects
+            .keys()
+            .filter(|x| !self.is_included(x))
+            .cloned()
+            .collect();
+
+        for path in excluded.iter() {
+            manifest.projects.remove(path);
+        }
+
+        for project in manifest.projects.values_mut() {
+            self.apply_fetch_options(project);
+        }
+
+        excluded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_project;
+
+    #[test]
+    fn rules_precedence() {
+        let rules: FetchRules = serde_json::from_str(r#"{
+            "exclude": ["prebuilts/**/darwin-*"],
+            "projects": [
+                { "path": "**", "fetch_lfs": false },
+                { "path": "external/*", "fetch_submodules": true },
+                { "path": "external/lfs-user", "fetch_lfs": true }
+            ]
+        }"#).unwrap();
+
+        assert!(rules.is_included(Path::new("prebuilts/clang/host/linux-x86")));
+        assert!(!rules.is_included(Path::new("prebuilts/clang/host/darwin-x86")));
+
+        let mut project = test_project("external/lfs-user", "https://example.com/lfs-user", "refs/heads/main");
+        rules.apply_fetch_options(&mut project);
+        assert!(project.repo_ref.fetch_lfs);
+        assert!(project.repo_ref.fetch_submodules);
+
+        let mut project = test_project("build/make", "https://example.com/build", "refs/heads/main");
+        rules.apply_fetch_options(&mut project);
+        assert!(!project.repo_ref.fetch_lfs);
+        assert!(!project.repo_ref.fetch_submodules);
+
+        let include_rules = FetchRules {
+            include: vec!["device/google".to_string()],
+            ..Default::default()
+        };
+        assert!(include_rules.is_included(Path::new("device/google/gs101")));
+        assert!(!include_rules.is_included(Path::new("device/generic/goldfish")));
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use clap::ValueEnum;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use crate::hash::{
+    encode_hex,
+    parse_sha256,
+    ParseHashError,
+};
+use crate::lock::{
+    Lock,
+    Lockset,
+};
+use crate::utils::{format_category, format_date};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SbomFormat {
+    /// SPDX 2.3 (JSON)
+    Spdx,
+    /// CycloneDX 1.5 (JSON)
+    Cyclonedx,
+}
+
+#[derive(Debug, Error)]
+pub enum GenerateSbomError {
+    #[error("invalid NAR hash of `{0}`")]
+    Hash(PathBuf, #[source] ParseHashError),
+    #[error("error serializing SBOM")]
+    Serialize(#[from] serde_json::Error),
+}
+
+/// License information found in the root of a project's locked store path.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DetectedLicenses {
+    /// The suffixes of AOSP's `MODULE_LICENSE_*` marker files, e.g. `APACHE2`.
+    pub module_licenses: BTreeSet<String>,
+    /// Files containing license texts or notices, like `LICENSE` or `NOTICE`.
+    pub license_files: BTreeSet<String>,
+}
+
+/// Looks for license markers and texts in `store_path`. Returns nothing if the store path
+/// doesn't exist, i.e. if the project hasn't been prefetched on this machine.
+pub async fn detect_licenses(store_path: &Path) -> DetectedLicenses {
+    let mut licenses = DetectedLicenses::default();
+    if store_path.as_os_str().is_empty() {
+        return licenses;
+    }
+    let Ok(mut dir) = fs::read_dir(store_path).await else {
+        return licenses;
+    };
+
+    while let Ok(Some(child)) = dir.next_entry().await {
+        let name = child.file_name().to_string_lossy().to_string();
+        if let Some(license) = name.strip_prefix("MODULE_LICENSE_") {
+            licenses.module_licenses.insert(license.to_string());
+        } else if ["LICENSE", "LICENCE", "COPYING", "NOTICE"].iter().any(|x| name.starts_with(x)) {
+            licenses.license_files.insert(name);
+        }
+    }
+
+    licenses
+}
+
+/// Maps a `MODULE_LICENSE_*` suffix to an SPDX license identifier, or to a `LicenseRef-` for
+/// the ones that are ambiguous (like `GPL` or `BSD`).
+fn module_license_id(license: &str) -> (String, bool) {
+    let spdx_id = match license {
+        "APACHE" | "APACHE2" => Some("Apache-2.0"),
+        "MIT" => Some("MIT"),
+        "ISC" => Some("ISC"),
+        "CC0" => Some("CC0-1.0"),
+        "W3C" => Some("W3C"),
+        _ => None,
+    };
+    match spdx_id {
+        Some(id) => (id.to_string(), true),
+        None => {
+            let idstring: String = license
+                .chars()
+                .map(|x| if x.is_ascii_alphanumeric() || x == '.' { x } else { '-' })
+                .collect();
+            (format!("LicenseRef-MODULE-LICENSE-{idstring}"), false)
+        },
+    }
+}
+
+struct SbomPackage {
+    path: PathBuf,
+    url: String,
+    lock: Lock,
+    nar_sha256: String,
+    categories: Vec<String>,
+    licenses: DetectedLicenses,
+}
+
+/// `SOURCE_DATE_EPOCH` if set, for reproducible documents, or the current time.
+fn creation_timestamp() -> String {
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0));
+    let seconds = timestamp % 86400;
+    format!("{}T{:02}:{:02}:{:02}Z", format_date(timestamp), seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxDocument {
+    spdx_version: &'static str,
+    data_license: &'static str,
+    #[serde(rename = "SPDXID")]
+    spdx_id: &'static str,
+    name: String,
+    document_namespace: String,
+    creation_info: SpdxCreationInfo,
+    packages: Vec<SpdxPackage>,
+    relationships: Vec<SpdxRelationship>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    has_extracted_licensing_infos: Vec<SpdxExtractedLicense>,
+}
+
+#[derive(Serialize)]
+struct SpdxCreationInfo {
+    created: String,
+    creators: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxPackage {
+    name: String,
+    #[serde(rename = "SPDXID")]
+    spdx_id: String,
+    version_info: String,
+    download_location: String,
+    files_analyzed: bool,
+    checksums: Vec<SpdxChecksum>,
+    source_info: String,
+    license_concluded: &'static str,
+    license_declared: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    license_comments: Option<String>,
+    copyright_text: &'static str,
+    comment: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxChecksum {
+    algorithm: &'static str,
+    checksum_value: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxRelationship {
+    spdx_element_id: &'static str,
+    relationship_type: &'static str,
+    related_spdx_element: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxExtractedLicense {
+    license_id: String,
+    name: String,
+    extracted_text: String,
+}
+
+fn spdx_document(name: &str, packages: &[SbomPackage]) -> SpdxDocument {
+    // The namespace only needs to be unique per document, so it's derived from the contents to
+    // keep the output reproducible.
+    let mut hasher = Sha256::new();
+    for package in packages {
+        hasher.update(package.path.as_os_str().as_encoded_bytes());
+        hasher.update(package.lock.commit.as_bytes());
+    }
+    let digest = encode_hex(&hasher.finalize());
+
+    let mut extracted_licenses = BTreeSet::new();
+    let mut spdx_ids = BTreeSet::new();
+    let mut spdx_packages = vec![];
+    let mut relationships = vec![];
+    for package in packages {
+        // SPDX IDs only allow letters, numbers, `.` and `-`, so different paths like `a/b-c` and
+        // `a-b/c` can map to the same ID. Later ones get a counter suffix to keep them unique.
+        let base_id = format!(
+            "SPDXRef-Package-{}",
+            package.path.to_string_lossy().chars().map(|x| if x.is_ascii_alphanumeric() || x == '.' { x } else { '-' }).collect::<String>(),
+        );
+        let mut spdx_id = base_id.clone();
+        let mut counter = 1;
+        while !spdx_ids.insert(spdx_id.clone()) {
+            counter += 1;
+            spdx_id = format!("{base_id}-{counter}");
+        }
+
+        let mut license_ids = vec![];
+        for license in package.licenses.module_licenses.iter() {
+            let (id, is_spdx) = module_license_id(license);
+            if !is_spdx {
+                extracted_licenses.insert((id.clone(), license.clone()));
+            }
+            license_ids.push(id);
+        }
+
+        spdx_packages.push(SpdxPackage {
+            name: package.path.to_string_lossy().to_string(),
+            spdx_id: spdx_id.clone(),
+            version_info: package.lock.commit.clone(),
+            download_location: format!("git+{}@{}", package.url, package.lock.commit),
+            files_analyzed: false,
+            checksums: vec![SpdxChecksum { algorithm: "SHA256", checksum_value: package.nar_sha256.clone() }],
+            source_info: "Fetched with Nix's fetchgit. The checksum is the SHA-256 hash of the NAR serialization of the checkout.".to_string(),
+            license_concluded: "NOASSERTION",
+            license_declared: if license_ids.is_empty() { "NOASSERTION".to_string() } else { license_ids.join(" AND ") },
+            license_comments: (!package.licenses.license_files.is_empty()).then(|| format!(
+                "License texts and notices in: {}",
+                package.licenses.license_files.iter().cloned().collect::<Vec<_>>().join(", "),
+            )),
+            copyright_text: "NOASSERTION",
+            comment: format!("Categories: {}", package.categories.join(", ")),
+        });
+        relationships.push(SpdxRelationship {
+            spdx_element_id: "SPDXRef-DOCUMENT",
+            relationship_type: "DESCRIBES",
+            related_spdx_element: spdx_id,
+        });
+    }
+
+    SpdxDocument {
+        spdx_version: "SPDX-2.3",
+        data_license: "CC0-1.0",
+        spdx_id: "SPDXRef-DOCUMENT",
+        name: name.to_string(),
+        document_namespace: format!("https://spdx.org/spdxdocs/{name}-{digest}"),
+        creation_info: SpdxCreationInfo {
+            created: creation_timestamp(),
+            creators: vec![format!("Tool: repo-tool-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        packages: spdx_packages,
+        relationships,
+        has_extracted_licensing_infos: extracted_licenses
+            .into_iter()
+            .map(|(license_id, license)| SpdxExtractedLicense {
+                license_id,
+                name: format!("MODULE_LICENSE_{license}"),
+                extracted_text: format!("The project declares its license with a `MODULE_LICENSE_{license}` file."),
+            })
+            .collect(),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CycloneDxBom {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: CycloneDxMetadata,
+    components: Vec<CycloneDxComponent>,
+}
+
+#[derive(Serialize)]
+struct CycloneDxMetadata {
+    timestamp: String,
+    tools: Vec<CycloneDxTool>,
+    component: CycloneDxMetadataComponent,
+}
+
+#[derive(Serialize)]
+struct CycloneDxTool {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+struct CycloneDxMetadataComponent {
+    #[serde(rename = "type")]
+    component_type: &'static str,
+    name: String,
+}
+
+#[derive(Serialize)]
+struct CycloneDxComponent {
+    #[serde(rename = "type")]
+    component_type: &'static str,
+    #[serde(rename = "bom-ref")]
+    bom_ref: String,
+    name: String,
+    version: String,
+    hashes: Vec<CycloneDxHash>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    licenses: Vec<CycloneDxLicenseChoice>,
+    #[serde(rename = "externalReferences")]
+    external_references: Vec<CycloneDxExternalReference>,
+    properties: Vec<CycloneDxProperty>,
+}
+
+#[derive(Serialize)]
+struct CycloneDxHash {
+    alg: &'static str,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct CycloneDxLicenseChoice {
+    license: CycloneDxLicense,
+}
+
+#[derive(Serialize)]
+struct CycloneDxLicense {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CycloneDxExternalReference {
+    #[serde(rename = "type")]
+    reference_type: &'static str,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct CycloneDxProperty {
+    name: &'static str,
+    value: String,
+}
+
+fn cyclonedx_bom(name: &str, packages: &[SbomPackage]) -> CycloneDxBom {
+    let components = packages
+        .iter()
+        .map(|package| {
+            let mut properties: Vec<_> = package.categories
+                .iter()
+                .map(|x| CycloneDxProperty { name: "repo2nix:category", value: x.clone() })
+                .collect();
+            properties.extend(package.licenses.license_files.iter().map(|x| CycloneDxProperty {
+                name: "repo2nix:license-file",
+                value: x.clone(),
+            }));
+
+            CycloneDxComponent {
+                component_type: "library",
+                bom_ref: package.path.to_string_lossy().to_string(),
+                name: package.path.to_string_lossy().to_string(),
+                version: package.lock.commit.clone(),
+                hashes: vec![CycloneDxHash { alg: "SHA-256", content: package.nar_sha256.clone() }],
+                licenses: package.licenses.module_licenses
+                    .iter()
+                    .map(|license| {
+                        let license = match module_license_id(license) {
+                            (id, true) => CycloneDxLicense { id: Some(id), name: None },
+                            (_, false) => CycloneDxLicense { id: None, name: Some(format!("MODULE_LICENSE_{license}")) },
+                        };
+                        CycloneDxLicenseChoice { license }
+                    })
+                    .collect(),
+                external_references: vec![CycloneDxExternalReference { reference_type: "vcs", url: package.url.clone() }],
+                properties,
+            }
+        })
+        .collect();
+
+    CycloneDxBom {
+        bom_format: "CycloneDX",
+        spec_version: "1.5",
+        version: 1,
+        metadata: CycloneDxMetadata {
+            timestamp: creation_timestamp(),
+            tools: vec![CycloneDxTool { name: "repo-tool", version: env!("CARGO_PKG_VERSION") }],
+            component: CycloneDxMetadataComponent { component_type: "operating-system", name: name.to_string() },
+        },
+        components,
+    }
+}
+
+/// Generates an SBOM with one package per active, locked project of a lockfile. Licenses are
+/// detected from the locked store paths where they exist.
+pub async fn generate_sbom(lockset: &Lockset, name: &str, format: SbomFormat) -> Result<String, GenerateSbomError> {
+    let mut packages = vec![];
+    for (path, entry) in lockset.entries.iter().filter(|(_, x)| x.project.active) {
+        let Some(lock) = &entry.lock else {
+            continue;
+        };
+        let nar_hash = parse_sha256(&lock.nix_hash).map_err(|e| GenerateSbomError::Hash(path.clone(), e))?;
+        packages.push(SbomPackage {
+            path: path.clone(),
+            url: entry.project.repo_ref.repo_url.to_string(),
+            lock: lock.clone(),
+            nar_sha256: encode_hex(&nar_hash),
+            categories: entry.project.categories.iter().map(format_category).collect(),
+            licenses: detect_licenses(&lock.path).await,
+        });
+    }
+
+    Ok(match format {
+        SbomFormat::Spdx => serde_json::to_string_pretty(&spdx_document(name, &packages))?,
+        SbomFormat::Cyclonedx => serde_json::to_string_pretty(&cyclonedx_bom(name, &packages))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[tokio::test]
+    async fn detect_project_licenses() {
+        let tmp = TempDir::new("sbom");
+        for name in ["MODULE_LICENSE_APACHE2", "MODULE_LICENSE_BSD_LIKE", "NOTICE", "LICENSE.txt", "README"] {
+            std::fs::write(tmp.path().join(name), "").unwrap();
+        }
+
+        let licenses = detect_licenses(tmp.path()).await;
+        assert_eq!(licenses, DetectedLicenses {
+            module_licenses: BTreeSet::from(["APACHE2".to_string(), "BSD_LIKE".to_string()]),
+            license_files: BTreeSet::from(["LICENSE.txt".to_string(), "NOTICE".to_string()]),
+        });
+        assert_eq!(module_license_id("APACHE2"), ("Apache-2.0".to_string(), true));
+        assert_eq!(module_license_id("BSD_LIKE"), ("LicenseRef-MODULE-LICENSE-BSD-LIKE".to_string(), false));
+        assert_eq!(detect_licenses(&tmp.path().join("missing")).await, DetectedLicenses::default());
+    }
+
+    fn sbom_packages() -> Vec<SbomPackage> {
+        let package = |path: &str, commit: &str, licenses: DetectedLicenses| SbomPackage {
+            path: PathBuf::from(path),
+            url: format!("https://android.googlesource.com/{path}"),
+            lock: Lock {
+                commit: commit.to_string(),
+                nix_hash: "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string(),
+                path: PathBuf::new(),
+                date: 1700000000,
+            },
+            nar_sha256: "00".repeat(32),
+            categories: vec!["default".to_string()],
+            licenses,
+        };
+        vec![
+            package("a/b-c", &"1".repeat(40), DetectedLicenses {
+                module_licenses: BTreeSet::from(["APACHE2".to_string(), "BSD".to_string()]),
+                license_files: BTreeSet::from(["NOTICE".to_string()]),
+            }),
+            package("a-b/c", &"2".repeat(40), DetectedLicenses::default()),
+        ]
+    }
+
+    #[test]
+    fn spdx_structure() {
+        let document = serde_json::to_value(spdx_document("lineage", &sbom_packages())).unwrap();
+        assert_eq!(document["spdxVersion"], "SPDX-2.3");
+        assert_eq!(document["SPDXID"], "SPDXRef-DOCUMENT");
+        assert!(document["documentNamespace"].as_str().unwrap().starts_with("https://spdx.org/spdxdocs/lineage-"));
+
+        let packages = document["packages"].as_array().unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0]["SPDXID"], "SPDXRef-Package-a-b-c");
+        assert_eq!(packages[1]["SPDXID"], "SPDXRef-Package-a-b-c-2");
+        assert_eq!(packages[0]["name"], "a/b-c");
+        assert_eq!(packages[0]["versionInfo"], "1".repeat(40));
+        assert_eq!(
+            packages[0]["downloadLocation"],
+            format!("git+https://android.googlesource.com/a/b-c@{}", "1".repeat(40)),
+        );
+        assert_eq!(packages[0]["checksums"][0]["algorithm"], "SHA256");
+        assert_eq!(packages[0]["licenseDeclared"], "Apache-2.0 AND LicenseRef-MODULE-LICENSE-BSD");
+        assert_eq!(packages[0]["licenseComments"], "License texts and notices in: NOTICE");
+        assert_eq!(packages[1]["licenseDeclared"], "NOASSERTION");
+        assert!(packages[1].get("licenseComments").is_none());
+
+        let relationships = document["relationships"].as_array().unwrap();
+        let described: Vec<_> = relationships.iter().map(|x| &x["relatedSpdxElement"]).collect();
+        assert_eq!(described, [&packages[0]["SPDXID"], &packages[1]["SPDXID"]]);
+        assert!(relationships.iter().all(|x| x["spdxElementId"] == "SPDXRef-DOCUMENT" && x["relationshipType"] == "DESCRIBES"));
+
+        let extracted = document["hasExtractedLicensingInfos"].as_array().unwrap();
+        assert_eq!(extracted.len(), 1);
+        assert_eq!(extracted[0]["licenseId"], "LicenseRef-MODULE-LICENSE-BSD");
+        assert_eq!(extracted[0]["name"], "MODULE_LICENSE_BSD");
+    }
+
+    #[test]
+    fn cyclonedx_structure() {
+        let bom = serde_json::to_value(cyclonedx_bom("lineage", &sbom_packages())).unwrap();
+        assert_eq!(bom["bomFormat"], "CycloneDX");
+        assert_eq!(bom["specVersion"], "1.5");
+        assert_eq!(bom["metadata"]["component"]["type"], "operating-system");
+        assert_eq!(bom["metadata"]["component"]["name"], "lineage");
+        assert_eq!(bom["metadata"]["tools"][0]["name"], "repo-tool");
+
+        let components = bom["components"].as_array().unwrap();
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0]["bom-ref"], "a/b-c");
+        assert_eq!(components[1]["bom-ref"], "a-b/c");
+        assert_eq!(components[0]["version"], "1".repeat(40));
+        assert_eq!(components[0]["hashes"][0]["alg"], "SHA-256");
+        assert_eq!(components[0]["hashes"][0]["content"], "00".repeat(32));
+        assert_eq!(components[0]["licenses"], serde_json::json!([
+            { "license": { "id": "Apache-2.0" } },
+            { "license": { "name": "MODULE_LICENSE_BSD" } },
+        ]));
+        assert!(components[1].get("licenses").is_none());
+        assert_eq!(components[0]["externalReferences"][0]["type"], "vcs");
+        assert_eq!(components[0]["externalReferences"][0]["url"], "https://android.googlesource.com/a/b-c");
+        assert_eq!(components[0]["properties"], serde_json::json!([
+            { "name": "repo2nix:category", "value": "default" },
+            { "name": "repo2nix:license-file", "value": "NOTICE" },
+        ]));
+    }
+}
@@ -0,0 +1,396 @@
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs;
+use tokio::process::Command;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use repo_manifest::git_url::GitUrl;
+use crate::fetch::mirrored_url;
+use crate::git_cache::{
+    bare_repo,
+    ensure_commit,
+    git,
+    GitError,
+};
+use crate::lock::Lockset;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureFormat {
+    OpenPgp,
+    Ssh,
+}
+
+/// A verified signature of a tag (or commit), as recorded in a lockfile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifiedSignature {
+    pub format: SignatureFormat,
+    /// The user ID of the OpenPGP key, or the principal from the allowed signers file.
+    pub signer: String,
+    /// The fingerprint of the (primary) OpenPGP key, or the SHA-256 fingerprint of the SSH key.
+    pub key: String,
+    /// The signed ref, i.e. a tag, or the commit ID for signed commits.
+    pub signed_ref: String,
+}
+
+#[derive(Debug, Error)]
+pub enum LoadTrustedKeysError {
+    #[error("error creating temporary GnuPG home")]
+    CreateHome(#[source] io::Error),
+    #[error("couldn't spawn `gpg` process")]
+    ProcessSpawn(#[source] io::Error),
+    #[error("error importing OpenPGP keys from `{0}`, stderr:\n{1}")]
+    Import(PathBuf, String),
+    #[error("allowed signers file `{0}` doesn't exist")]
+    MissingAllowedSigners(PathBuf),
+}
+
+#[derive(Debug, Error)]
+pub enum VerifySignatureError {
+    #[error("error fetching `{0}`")]
+    Git(String, #[source] GitError),
+    #[error("`{0}` points to {1} instead of the locked commit {2}")]
+    TagMismatch(String, String, String),
+    #[error("`{0}` points to a tag object named `{1}`")]
+    TagNameMismatch(String, String),
+    #[error("couldn't spawn `git` process")]
+    ProcessSpawn(#[from] io::Error),
+    #[error("`{0}` has no valid signature by a trusted key:\n{1}")]
+    Invalid(String, String),
+}
+
+#[derive(Debug, Error)]
+#[error("error verifying signature of `{0}`")]
+pub struct VerifyProjectSignatureError(PathBuf, #[source] VerifySignatureError);
+
+static GNUPG_HOME_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The keys signatures are verified against: OpenPGP keys, which are imported into a private,
+/// temporary GnuPG home (so that the user's keyring is never consulted), and an SSH allowed
+/// signers file as understood by `ssh-keygen -Y verify`.
+#[derive(Debug)]
+pub struct TrustedKeys {
+    gnupg_home: PathBuf,
+    allowed_signers: Option<PathBuf>,
+}
+
+impl TrustedKeys {
+    pub async fn load(openpgp_keys: &[PathBuf], allowed_signers: Option<&Path>) -> Result<Self, LoadTrustedKeysError> {
+        let gnupg_home = env::temp_dir().join(format!(
+            "repo-tool-gnupg-{}-{}",
+            std::process::id(),
+            GNUPG_HOME_COUNTER.fetch_add(1, Ordering::Relaxed),
+        ));
+        let _ = fs::remove_dir_all(&gnupg_home).await;
+        fs::create_dir_all(&gnupg_home).await.map_err(LoadTrustedKeysError::CreateHome)?;
+        // Create the struct right away, so that the home is cleaned up on errors.
+        let mut keys = TrustedKeys { gnupg_home, allowed_signers: None };
+
+        for path in openpgp_keys {
+            let output = Command::new("gpg")
+                .arg("--homedir")
+                .arg(&keys.gnupg_home)
+                .args(["--batch", "--quiet", "--import"])
+                .arg(path)
+                .output()
+                .await
+                .map_err(LoadTrustedKeysError::ProcessSpawn)?;
+            if !output.status.success() {
+                return Err(LoadTrustedKeysError::Import(path.clone(), String::from_utf8_lossy(&output.stderr).to_string()));
+            }
+        }
+
+        if let Some(allowed_signers) = allowed_signers {
+            // Relative paths wouldn't work with `--git-dir`.
+            let path = std::path::absolute(allowed_signers)
+                .map_err(|_| LoadTrustedKeysError::MissingAllowedSigners(allowed_signers.to_path_buf()))?;
+            if !fs::try_exists(&path).await.unwrap_or(false) {
+                return Err(LoadTrustedKeysError::MissingAllowedSigners(allowed_signers.to_path_buf()));
+            }
+            keys.allowed_signers = Some(path);
+        }
+
+        Ok(keys)
+    }
+
+    /// Runs `git verify-tag` or `git verify-commit`, and returns the signature on success.
+    async fn verify(&self, git_dir: &Path, command: &str, object: &str) -> Result<VerifiedSignature, VerifySignatureError> {
+        let allowed_signers = self.allowed_signers.as_deref().unwrap_or(Path::new("/dev/null"));
+        let output = Command::new("git")
+            .env("GNUPGHOME", &self.gnupg_home)
+            .arg("--git-dir")
+            .arg(git_dir)
+            .arg("-c")
+            .arg(format!("gpg.ssh.allowedSignersFile={}", allowed_signers.display()))
+            .args([command, "--raw", object])
+            .stdin(Stdio::null())
+            .output()
+            .await?;
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        if !output.status.success() {
+            return Err(VerifySignatureError::Invalid(object.to_string(), stderr));
+        }
+
+        parse_signature(&stderr, object).ok_or(VerifySignatureError::Invalid(object.to_string(), stderr))
+    }
+}
+
+impl Drop for TrustedKeys {
+    fn drop(&mut self) {
+        // `gpg --import` may have started an agent for the temporary home.
+        let _ = std::process::Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(&self.gnupg_home)
+            .args(["--kill", "all"])
+            .stderr(Stdio::null())
+            .status();
+        let _ = std::fs::remove_dir_all(&self.gnupg_home);
+    }
+}
+
+/// Parses the output of `git verify-tag --raw`/`git verify-commit --raw`, i.e. GnuPG's status
+/// lines for OpenPGP signatures and `ssh-keygen`'s message for SSH signatures.
+fn parse_signature(output: &str, signed_ref: &str) -> Option<VerifiedSignature> {
+    let mut signer = None;
+    let mut key = None;
+    for line in output.lines() {
+        if let Some(rest) = line.strip_prefix("[GNUPG:] GOODSIG ") {
+            signer = rest.split_once(' ').map(|(_, uid)| uid.to_string());
+        } else if let Some(rest) = line.strip_prefix("[GNUPG:] VALIDSIG ") {
+            // The last field is the fingerprint of the primary key, if the signing key is a
+            // subkey.
+            let fields: Vec<_> = rest.split(' ').collect();
+            key = fields.get(9).or(fields.first()).map(|x| x.to_string());
+        } else if let Some(rest) = line.strip_prefix("Good \"git\" signature for ") {
+            let (principal, rest) = rest.split_once(" with ")?;
+            let fingerprint = rest.rsplit(' ').next()?;
+            return Some(VerifiedSignature {
+                format: SignatureFormat::Ssh,
+                signer: principal.to_string(),
+                key: fingerprint.to_string(),
+                signed_ref: signed_ref.to_string(),
+            });
+        }
+    }
+
+    Some(VerifiedSignature {
+        format: SignatureFormat::OpenPgp,
+        signer: signer?,
+        key: key?,
+        signed_ref: signed_ref.to_string(),
+    })
+}
+
+/// The name in the `tag` header of an annotated tag object.
+fn parse_tag_name(tag_object: &str) -> Option<&str> {
+    tag_object
+        .lines()
+        .take_while(|x| !x.is_empty())
+        .find_map(|x| x.strip_prefix("tag "))
+}
+
+/// Verifies that `git_ref` of the repo at `repo_url` is signed by one of the trusted keys and
+/// points to `commit`. Tags need to be signed annotated tags whose name matches the ref, so that a
+/// signed tag can't be published under another name; for any other ref, the commit itself needs
+/// to be signed. The objects are fetched into the bare repos in `git_cache_dir`.
+pub async fn verify_ref_signature(git_cache_dir: &Path, keys: &TrustedKeys, repo_url: &GitUrl, git_ref: &str, commit: &str) -> Result<VerifiedSignature, VerifySignatureError> {
+    let git_error = |e| VerifySignatureError::Git(repo_url.to_string(), e);
+    fs::create_dir_all(git_cache_dir).await?;
+    let git_dir = bare_repo(git_cache_dir, repo_url).await.map_err(git_error)?;
+
+    if git_ref.starts_with("refs/tags/") {
+        let url = mirrored_url(&repo_url.to_string());
+        let refspec = format!("+{git_ref}:{git_ref}");
+        git(&git_dir, &["fetch", "--quiet", "--no-tags", "--filter=tree:0", &url, &refspec]).await.map_err(git_error)?;
+        let output = git(&git_dir, &["rev-parse", &format!("{git_ref}^{{commit}}")]).await.map_err(git_error)?;
+        let tagged = String::from_utf8_lossy(&output).trim().to_string();
+        if tagged != commit {
+            return Err(VerifySignatureError::TagMismatch(git_ref.to_string(), tagged, commit.to_string()));
+        }
+        let signature = keys.verify(&git_dir, "verify-tag", git_ref).await?;
+
+        let output = git(&git_dir, &["cat-file", "tag", git_ref]).await.map_err(git_error)?;
+        let tag_object = String::from_utf8_lossy(&output);
+        let name = parse_tag_name(&tag_object).unwrap_or_default();
+        if Some(name) != git_ref.strip_prefix("refs/tags/") {
+            return Err(VerifySignatureError::TagNameMismatch(git_ref.to_string(), name.to_string()));
+        }
+        Ok(signature)
+    } else {
+        ensure_commit(&git_dir, repo_url, commit).await.map_err(git_error)?;
+        keys.verify(&git_dir, "verify-commit", commit).await
+    }
+}
+
+/// Verifies the revisions of all active, locked projects in `lockset` and records their
+/// signatures. Projects tracking a tag need a signed tag, all others a signed commit.
+pub async fn verify_project_signatures(lockset: &mut Lockset, git_cache_dir: &Path, keys: &TrustedKeys) -> Result<(), VerifyProjectSignatureError> {
+    for (path, entry) in lockset.entries.iter_mut().filter(|(_, x)| x.project.active) {
+        let Some(lock) = &entry.lock else {
+            continue;
+        };
+
+        eprintln!("Verifying signature of `{}`...", path.display());
+        let repo_ref = &entry.project.repo_ref;
+        let signature = verify_ref_signature(git_cache_dir, keys, &repo_ref.repo_url, &repo_ref.revision, &lock.commit)
+            .await
+            .map_err(|e| VerifyProjectSignatureError(path.clone(), e))?;
+        entry.signature = Some(signature);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::lock::Lock;
+    use crate::test_utils::{TempDir, git as test_git, init_repo, file_url, test_project};
+
+    fn generate_ssh_key(dir: &Path) -> PathBuf {
+        let key = dir.join("key");
+        let status = std::process::Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "test", "-f"])
+            .arg(&key)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let public_key = std::fs::read_to_string(dir.join("key.pub")).unwrap();
+        std::fs::write(dir.join("allowed_signers"), format!("release@example.com {public_key}")).unwrap();
+        key
+    }
+
+    #[tokio::test]
+    async fn verify_ssh_signed_tags() {
+        let tmp = TempDir::new("signatures");
+        let key = generate_ssh_key(tmp.path());
+        std::fs::write(tmp.path().join("other_signers"), "").unwrap();
+
+        let repo = tmp.path().join("manifest");
+        let commit = init_repo(&repo);
+        let signing_key = format!("user.signingkey={}", key.display());
+        test_git(&repo, &["-c", "gpg.format=ssh", "-c", &signing_key, "tag", "-s", "-m", "Release", "signed"]);
+        test_git(&repo, &["tag", "-m", "Release", "unsigned"]);
+        let url = GitUrl::parse(&file_url(&repo)).unwrap();
+        let cache = tmp.path().join("cache");
+
+        let keys = TrustedKeys::load(&[], Some(&tmp.path().join("allowed_signers"))).await.unwrap();
+        let signature = verify_ref_signature(&cache, &keys, &url, "refs/tags/signed", &commit).await.unwrap();
+        assert_eq!(signature.format, SignatureFormat::Ssh);
+        assert_eq!(signature.signer, "release@example.com");
+        assert!(signature.key.starts_with("SHA256:"));
+        assert!(matches!(
+            verify_ref_signature(&cache, &keys, &url, "refs/tags/unsigned", &commit).await,
+            Err(VerifySignatureError::Invalid(..))
+        ));
+        assert!(matches!(
+            verify_ref_signature(&cache, &keys, &url, "refs/tags/signed", &"0".repeat(40)).await,
+            Err(VerifySignatureError::TagMismatch(..))
+        ));
+
+        // The same signed tag object published under another name.
+        test_git(&repo, &["update-ref", "refs/tags/renamed", "refs/tags/signed"]);
+        assert!(matches!(
+            verify_ref_signature(&cache, &keys, &url, "refs/tags/renamed", &commit).await,
+            Err(VerifySignatureError::TagNameMismatch(tag, name)) if tag == "refs/tags/renamed" && name == "signed"
+        ));
+
+        let other_keys = TrustedKeys::load(&[], Some(&tmp.path().join("other_signers"))).await.unwrap();
+        assert!(matches!(
+            verify_ref_signature(&cache, &other_keys, &url, "refs/tags/signed", &commit).await,
+            Err(VerifySignatureError::Invalid(..))
+        ));
+    }
+
+    /// Runs `command` with `GNUPGHOME` set to `gnupg_home`, and returns its trimmed stdout.
+    fn run_with_gnupg_home(gnupg_home: &Path, command: &str, args: &[&str]) -> String {
+        let output = std::process::Command::new(command)
+            .env("GNUPGHOME", gnupg_home)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{command} {args:?} failed: {}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn verify_openpgp_signed_tags() {
+        let tmp = TempDir::new("openpgp-signatures");
+        let gnupg_home = tmp.path().join("gnupg");
+        std::fs::create_dir(&gnupg_home).unwrap();
+        run_with_gnupg_home(&gnupg_home, "gpg", &[
+            "--batch", "--quiet", "--passphrase", "", "--quick-gen-key", "Release <release@example.com>", "ed25519", "sign", "never",
+        ]);
+        let fingerprint = run_with_gnupg_home(&gnupg_home, "gpg", &["--batch", "--with-colons", "--list-keys"])
+            .lines()
+            .find_map(|x| x.strip_prefix("fpr:"))
+            .map(|x| x.trim_matches(':').to_string())
+            .unwrap();
+        let public_key = tmp.path().join("release.asc");
+        let exported = run_with_gnupg_home(&gnupg_home, "gpg", &["--batch", "--armor", "--export", &fingerprint]);
+        std::fs::write(&public_key, exported).unwrap();
+
+        let repo = tmp.path().join("manifest");
+        let commit = init_repo(&repo);
+        let signing_key = format!("user.signingkey={fingerprint}");
+        run_with_gnupg_home(&gnupg_home, "git", &[
+            "-C", &repo.to_string_lossy(), "-c", "user.name=Test", "-c", "user.email=test@example.com",
+            "-c", &signing_key, "tag", "-s", "-m", "Release", "signed",
+        ]);
+        let _ = std::process::Command::new("gpgconf").env("GNUPGHOME", &gnupg_home).args(["--kill", "all"]).status();
+        let url = GitUrl::parse(&file_url(&repo)).unwrap();
+        let cache = tmp.path().join("cache");
+
+        let keys = TrustedKeys::load(&[public_key], None).await.unwrap();
+        let signature = verify_ref_signature(&cache, &keys, &url, "refs/tags/signed", &commit).await.unwrap();
+        assert_eq!(signature, VerifiedSignature {
+            format: SignatureFormat::OpenPgp,
+            signer: "Release <release@example.com>".to_string(),
+            key: fingerprint,
+            signed_ref: "refs/tags/signed".to_string(),
+        });
+
+        let other_keys = TrustedKeys::load(&[], None).await.unwrap();
+        assert!(matches!(
+            verify_ref_signature(&cache, &other_keys, &url, "refs/tags/signed", &commit).await,
+            Err(VerifySignatureError::Invalid(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn verify_project_tags() {
+        let tmp = TempDir::new("project-signatures");
+        let key = generate_ssh_key(tmp.path());
+        let repo = tmp.path().join("remote/build");
+        let commit = init_repo(&repo);
+        let signing_key = format!("user.signingkey={}", key.display());
+        test_git(&repo, &["-c", "gpg.format=ssh", "-c", &signing_key, "tag", "-s", "-m", "Release", "release"]);
+
+        let url = file_url(&repo);
+        let projects = HashMap::from(["build/make", "build/soong"].map(|path| {
+            (PathBuf::from(path), test_project(path, &url, "refs/tags/release"))
+        }));
+        let mut lockset = Lockset::new(&projects, &tmp.path().join("test.lock"));
+        lockset.entries.get_mut(Path::new("build/make")).unwrap().lock = Some(Lock {
+            commit: commit.clone(),
+            nix_hash: String::new(),
+            path: PathBuf::new(),
+            date: 0,
+        });
+
+        let cache = tmp.path().join("cache");
+        let keys = TrustedKeys::load(&[], Some(&tmp.path().join("allowed_signers"))).await.unwrap();
+        verify_project_signatures(&mut lockset, &cache, &keys).await.unwrap();
+        let signature = lockset.entries[Path::new("build/make")].signature.as_ref().unwrap();
+        assert_eq!(signature.signed_ref, "refs/tags/release");
+        assert!(lockset.entries[Path::new("build/soong")].signature.is_none());
+
+        // Projects tracking a branch need signed commits.
+        lockset.entries.get_mut(Path::new("build/make")).unwrap().project.repo_ref.revision = "refs/heads/main".to_string();
+        assert!(verify_project_signatures(&mut lockset, &cache, &keys).await.is_err());
+    }
+}
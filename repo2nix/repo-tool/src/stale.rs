@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use serde::Serialize;
+use thiserror::Error;
+use crate::fetch::{
+    git_ls_remote,
+    GitLsRemoteError,
+};
+use crate::git_cache::{
+    bare_repo,
+    commit_date,
+    fetch_ref,
+    is_ancestor,
+    GitError,
+};
+use crate::lock::{
+    is_commit_id,
+    Lock,
+    Lockset,
+    LocksetEntry,
+};
+use crate::utils::{format_category, format_date};
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NewerCommit {
+    pub commit: String,
+    /// Commit date as UNIX timestamp.
+    pub date: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StaleProject {
+    pub commit: String,
+    /// Commit date of the locked commit as UNIX timestamp.
+    pub date: u64,
+    pub age_days: u64,
+    /// The newest commit of the tracked revision, if it's newer than the locked one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub newer: Option<NewerCommit>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StaleReport {
+    pub max_age_days: u64,
+    /// Stale projects by category (as formatted by `format_category`) and path. Projects in
+    /// several categories are listed under each of them.
+    pub categories: BTreeMap<String, BTreeMap<PathBuf, StaleProject>>,
+}
+
+#[derive(Debug, Error)]
+pub enum FindStaleProjectsError {
+    #[error("error creating git cache directory")]
+    CreateCacheDir(#[source] io::Error),
+    #[error("error resolving revision of `{0}`")]
+    LsRemote(PathBuf, #[source] GitLsRemoteError),
+    #[error("error looking up newest commit of `{0}`")]
+    Git(PathBuf, #[source] GitError),
+}
+
+/// Looks up the newest commit of the revision a project tracks, if it's newer than the locked
+/// one, i.e. if it descends from the locked commit or was committed after it. A revision that was
+/// rewound or force-pushed to older history doesn't count. Projects locked to a commit, or whose
+/// revision doesn't exist anymore, are skipped.
+async fn newer_commit(git_cache_dir: &Path, path: &Path, entry: &LocksetEntry, lock: &Lock) -> Result<Option<NewerCommit>, FindStaleProjectsError> {
+    let repo_ref = &entry.project.repo_ref;
+    if is_commit_id(&repo_ref.revision) {
+        return Ok(None);
+    }
+
+    match git_ls_remote(&repo_ref.repo_url.to_string(), &repo_ref.revision).await {
+        Ok(commit) if commit == lock.commit => return Ok(None),
+        Ok(_) => (),
+        Err(GitLsRemoteError::RevNotFound) => return Ok(None),
+        Err(e) => return Err(FindStaleProjectsError::LsRemote(path.to_path_buf(), e)),
+    }
+
+    let git_error = |e| FindStaleProjectsError::Git(path.to_path_buf(), e);
+    let git_dir = bare_repo(git_cache_dir, &repo_ref.repo_url).await.map_err(git_error)?;
+    let commit = fetch_ref(&git_dir, &repo_ref.repo_url, &repo_ref.revision).await.map_err(git_error)?;
+    let date = commit_date(&git_dir, &commit).await.map_err(git_error)?;
+    if date > lock.date || is_ancestor(&git_dir, &lock.commit, &commit).await {
+        Ok(Some(NewerCommit { commit, date }))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Finds the active projects whose locked commit is older than `max_age_days` at `now`. With a
+/// `git_cache_dir`, projects whose tracked revision moved on since they were locked are reported
+/// as well, along with the newest commit of the revision.
+pub async fn find_stale_projects(lockset: &Lockset, max_age_days: u64, now: u64, git_cache_dir: Option<&Path>) -> Result<StaleReport, FindStaleProjectsError> {
+    if let Some(git_cache_dir) = git_cache_dir {
+        fs::create_dir_all(git_cache_dir).await.map_err(FindStaleProjectsError::CreateCacheDir)?;
+    }
+
+    let mut report = StaleReport { max_age_days, ..Default::default() };
+    for (path, entry) in lockset.entries.iter().filter(|(_, x)| x.project.active) {
+        let Some(lock) = &entry.lock else {
+            continue;
+        };
+
+        let newer = match git_cache_dir {
+            Some(git_cache_dir) => {
+                eprintln!("Checking `{}`...", path.display());
+                newer_commit(git_cache_dir, path, entry, lock).await?
+            },
+            None => None,
+        };
+        let age_days = now.saturating_sub(lock.date) / SECONDS_PER_DAY;
+        if age_days < max_age_days && newer.is_none() {
+            continue;
+        }
+
+        let project = StaleProject { commit: lock.commit.clone(), date: lock.date, age_days, newer };
+        for category in entry.project.categories.iter() {
+            report.categories
+                .entry(format_category(category))
+                .or_default()
+                .insert(path.clone(), project.clone());
+        }
+    }
+
+    Ok(report)
+}
+
+impl fmt::Display for StaleReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.categories.is_empty() {
+            return writeln!(f, "No stale projects.");
+        }
+
+        for (category, projects) in self.categories.iter() {
+            writeln!(f, "{category}:")?;
+            for (path, project) in projects.iter() {
+                write!(f, "  {}: locked {} ({} days old)", path.display(), format_date(project.date), project.age_days)?;
+                if let Some(newer) = &project.newer {
+                    write!(f, ", revision has newer commits up to {}", format_date(newer.date))?;
+                }
+                writeln!(f)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use repo_manifest::resolver::Category;
+    use crate::test_utils::{test_project, TempDir, git, init_repo, file_url};
+
+    #[tokio::test]
+    async fn report_stale_projects() {
+        let tmp = TempDir::new("stale");
+        let repo = tmp.path().join("remote/device");
+        let old_commit = init_repo(&repo);
+        let now = 1700000000 + 400 * SECONDS_PER_DAY;
+
+        let url = file_url(&repo);
+        let projects = HashMap::from(["device/google/oriole", "device/google/raven", "build/make"].map(|path| {
+            (PathBuf::from(path), test_project(path, &url, "refs/heads/main"))
+        }));
+        let mut lockset = Lockset::new(&projects, &tmp.path().join("test.lock"));
+        for (path, date) in [("device/google/oriole", 1700000000), ("device/google/raven", 1700000000), ("build/make", now)] {
+            let entry = lockset.entries.get_mut(Path::new(path)).unwrap();
+            entry.lock = Some(Lock { commit: old_commit.clone(), nix_hash: String::new(), path: PathBuf::new(), date });
+        }
+        lockset.entries.get_mut(Path::new("device/google/raven")).unwrap().project.categories =
+            [Category::DeviceSpecific("raven".to_string())].into();
+
+        let report = find_stale_projects(&lockset, 365, now, None).await.unwrap();
+        assert_eq!(report.categories.keys().collect::<Vec<_>>(), ["default", "device:raven"]);
+        assert_eq!(report.categories["default"].keys().collect::<Vec<_>>(), [Path::new("device/google/oriole")]);
+        assert_eq!(report.categories["default"][Path::new("device/google/oriole")].age_days, 400);
+
+        // A commit on top of the locked one makes `build/make` stale as well, even though its
+        // date isn't later than the lock's.
+        git(&repo, &["commit", "--quiet", "--allow-empty", "-m", "Update"]);
+        let new_commit = git(&repo, &["rev-parse", "HEAD"]);
+        let cache_dir = tmp.path().join("cache");
+        let report = find_stale_projects(&lockset, 365, now, Some(&cache_dir)).await.unwrap();
+        assert_eq!(report.categories["default"][Path::new("build/make")].newer, Some(NewerCommit {
+            commit: new_commit.clone(),
+            date: 1700000000,
+        }));
+
+        // Rewinding `main` to an older commit doesn't.
+        let entry = lockset.entries.get_mut(Path::new("build/make")).unwrap();
+        entry.lock = Some(Lock { commit: new_commit, nix_hash: String::new(), path: PathBuf::new(), date: now });
+        git(&repo, &["reset", "--quiet", "--hard", &old_commit]);
+        let report = find_stale_projects(&lockset, 365, now, Some(&cache_dir)).await.unwrap();
+        assert!(!report.categories["default"].contains_key(Path::new("build/make")));
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use repo_manifest::git_url::GitUrl;
+use repo_manifest::resolver::{
+    Project,
+    GitRepoRef,
+    Category,
+};
+
+pub fn test_project(path: &str, url: &str, revision: &str) -> Project {
+    Project {
+        path: PathBuf::from(path),
+        groups: vec![],
+        linkfiles: vec![],
+        copyfiles: vec![],
+        repo_ref: GitRepoRef {
+            repo_url: GitUrl::parse(url).unwrap(),
+            revision: revision.to_string(),
+            fetch_lfs: true,
+            fetch_submodules: false,
+        },
+        categories: BTreeSet::from([Category::Default]),
+        lineage_deps: None,
+        active: true,
+    }
+}
+
+/// A temporary directory that is deleted again when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("repo-tool-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs `git` with a fixed identity and without any user or system configuration, and returns
+/// its trimmed stdout.
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_AUTHOR_NAME", "Test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_AUTHOR_DATE", "1700000000 +0000")
+        .env("GIT_COMMITTER_NAME", "Test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_DATE", "1700000000 +0000")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {args:?} failed: {}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// Creates a git repository with a single commit on `main` at `path`, and returns the commit ID.
+pub fn init_repo(path: &Path) -> String {
+    fs::create_dir_all(path).unwrap();
+    git(path, &["init", "--quiet", "--initial-branch=main"]);
+    fs::write(path.join("README"), path.to_string_lossy().as_bytes()).unwrap();
+    git(path, &["add", "README"]);
+    git(path, &["commit", "--quiet", "-m", "Initial commit"]);
+    git(path, &["rev-parse", "HEAD"])
+}
+
+pub fn file_url(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
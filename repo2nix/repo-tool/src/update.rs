@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+use thiserror::Error;
+use crate::lock::{
+    Lockset,
+    UpdateLocksetError,
+};
+use crate::utils::path_matches_glob;
+
+#[derive(Debug, Error)]
+pub enum UpdateProjectsError {
+    #[error("no active project matches `{0}`")]
+    UnmatchedPattern(String),
+    #[error("error updating lock of `{0}`")]
+    Update(PathBuf, #[source] UpdateLocksetError),
+}
+
+/// Re-resolves the refs of all active projects matching one of `patterns` (or of all active
+/// projects, if there are none) and updates their locks, optionally switching them to
+/// `revision` first. All other entries are left untouched. Returns the selected paths.
+pub async fn update_projects(lockset: &mut Lockset, patterns: &[String], revision: Option<&str>) -> Result<Vec<PathBuf>, UpdateProjectsError> {
+    for pattern in patterns {
+        if !lockset.entries.iter().any(|(path, x)| x.project.active && path_matches_glob(path, pattern)) {
+            return Err(UpdateProjectsError::UnmatchedPattern(pattern.clone()));
+        }
+    }
+
+    let paths: Vec<_> = lockset
+        .entries
+        .iter()
+        .filter(|(path, x)| x.project.active && (patterns.is_empty() || patterns.iter().any(|p| path_matches_glob(path, p))))
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    lockset.cache_own_locks();
+    for (i, path) in paths.iter().enumerate() {
+        if let Some(revision) = revision {
+            lockset.entries.get_mut(path).unwrap().project.repo_ref.revision = revision.to_string();
+        }
+        eprintln!("Updating lock for `{}` ({}/{})", path.display(), i+1, paths.len());
+        lockset.update(path)
+            .await
+            .map_err(|e| UpdateProjectsError::Update(path.clone(), e))?;
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use crate::lock::Lock;
+    use crate::test_utils::{test_project, TempDir, init_repo, file_url};
+
+    #[tokio::test]
+    async fn update_selected_projects() {
+        let tmp = TempDir::new("update");
+        let commit = init_repo(&tmp.path().join("build"));
+        let url = file_url(&tmp.path().join("build"));
+        let projects = HashMap::from(["build/make", "build/soong", "art"].map(|path| {
+            (PathBuf::from(path), test_project(path, &url, "refs/heads/does-not-exist"))
+        }));
+        let mut lockset = Lockset::new(&projects, &tmp.path().join("test.lock"));
+        for entry in lockset.entries.values_mut() {
+            entry.lock = Some(Lock {
+                commit: commit.clone(),
+                nix_hash: String::new(),
+                path: PathBuf::new(),
+                date: 0,
+            });
+        }
+
+        assert!(matches!(
+            update_projects(&mut lockset, &["external/*".to_string()], None).await,
+            Err(UpdateProjectsError::UnmatchedPattern(_))
+        ));
+
+        // The locks are up to date, so nothing needs to be prefetched.
+        let updated = update_projects(&mut lockset, &["build/*".to_string()], Some("refs/heads/main")).await.unwrap();
+        assert_eq!(updated, [PathBuf::from("build/make"), PathBuf::from("build/soong")]);
+        assert_eq!(lockset.entries[Path::new("build/make")].project.repo_ref.revision, "refs/heads/main");
+        assert_eq!(lockset.entries[Path::new("art")].project.repo_ref.revision, "refs/heads/does-not-exist");
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::BTreeSet;
+use std::path::Path;
 use repo_manifest::resolver::{
     Category,
 };
@@ -40,7 +41,7 @@ pub async fn cleanup_broken_projects(lockfile: &mut Lockset, group: Option<&str>
                 project.repo_ref.revision
             );
             match git_ls_remote(
-                &project.repo_ref.repo_url.as_str(),
+                &project.repo_ref.repo_url.to_string(),
                 &project.repo_ref.revision
             ).await {
                 Ok(_) => (),
@@ -59,3 +60,101 @@ pub async fn cleanup_broken_projects(lockfile: &mut Lockset, group: Option<&str>
     }
     Ok(removed_entries)
 }
+
+/// Formats a category as `default` or `device:<device>`, as used in reports and on the command
+/// line.
+pub fn format_category(category: &Category) -> String {
+    match category {
+        Category::Default => "default".to_string(),
+        Category::DeviceSpecific(device) => format!("device:{device}"),
+    }
+}
+
+/// Parses a category formatted by `format_category`.
+pub fn parse_category(text: &str) -> Result<Category, String> {
+    match text.strip_prefix("device:") {
+        Some(device) if !device.is_empty() => Ok(Category::DeviceSpecific(device.to_string())),
+        _ if text == "default" => Ok(Category::Default),
+        _ => Err(format!("invalid category `{text}`, expected `default` or `device:<device>`")),
+    }
+}
+
+/// Formats a UNIX timestamp as `YYYY-MM-DD` (UTC).
+pub fn format_date(timestamp: u64) -> String {
+    // Howard Hinnant's `civil_from_days`
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+fn segment_matches_glob(segment: &[u8], pattern: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => segment.is_empty(),
+        Some((b'*', rest)) => (0..=segment.len()).any(|i| segment_matches_glob(&segment[i..], rest)),
+        Some((b'?', rest)) => !segment.is_empty() && segment_matches_glob(&segment[1..], rest),
+        Some((c, rest)) => segment.first() == Some(c) && segment_matches_glob(&segment[1..], rest),
+    }
+}
+
+fn segments_match_glob(segments: &[&str], pattern: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => segments.is_empty(),
+        Some((&"**", rest)) => (0..=segments.len()).any(|i| segments_match_glob(&segments[i..], rest)),
+        Some((p, rest)) => match segments.split_first() {
+            Some((s, segments_rest)) => segment_matches_glob(s.as_bytes(), p.as_bytes()) && segments_match_glob(segments_rest, rest),
+            None => false,
+        },
+    }
+}
+
+/// Matches a project path against a glob pattern, where `*` and `?` match within a single path
+/// component and `**` matches any number of path components. A pattern also matches everything
+/// below the paths it matches, so `device/google` matches `device/google/gs101` as well.
+pub fn path_matches_glob(path: &Path, pattern: &str) -> bool {
+    let segments: Vec<_> = path
+        .components()
+        .filter_map(|x| x.as_os_str().to_str())
+        .collect();
+    let pattern: Vec<_> = pattern
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .collect();
+
+    (1..=segments.len()).any(|i| segments_match_glob(&segments[..i], &pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matching() {
+        let cases = [
+            ("build/make", "build/make", true),
+            ("build/make", "build", true),
+            ("build/make", "build/", true),
+            ("build/make", "bui", false),
+            ("build/make", "build/m?ke", true),
+            ("build/make", "*/make", true),
+            ("build/make", "*", true),
+            ("device/google/gs101/prebuilts", "device/*/gs101", true),
+            ("device/google/gs101", "**/gs101", true),
+            ("gs101", "**/gs101", true),
+            ("device/google/gs101", "device/**", true),
+            ("device/google/gs101", "vendor/**", false),
+            ("prebuilts/clang/host/linux-x86", "prebuilts/**/linux-*", true),
+            ("prebuilts/clang/host/darwin-x86", "prebuilts/**/linux-*", false),
+        ];
+
+        for (path, pattern, expected) in cases {
+            assert_eq!(path_matches_glob(Path::new(path), pattern), expected, "`{path}` vs `{pattern}`");
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use serde::Serialize;
+use thiserror::Error;
+use crate::fetch::{
+    git_ls_remote,
+    nix_prefetch_git,
+    GitLsRemoteError,
+    NixPrefetchGitError,
+};
+use crate::git_cache::{
+    bare_repo,
+    ensure_commit,
+    fetch_ref,
+    git,
+    is_ancestor,
+    GitError,
+};
+use crate::lock::{
+    is_commit_id,
+    Lock,
+    Lockset,
+    LocksetEntry,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+    NotLocked,
+    /// The locked revision doesn't exist at the remote anymore.
+    RevisionNotFound,
+    /// The locked commit can't be fetched from the remote, or isn't reachable from the locked
+    /// revision anymore (e.g. because a branch was force-pushed).
+    CommitUnavailable,
+    /// The `src` of a `linkfile` or `copyfile` doesn't exist in the locked tree.
+    MissingSource { src: PathBuf },
+    HashMismatch { locked: String, actual: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NotLocked => write!(f, "not locked"),
+            Problem::RevisionNotFound => write!(f, "revision doesn't exist at the remote anymore"),
+            Problem::CommitUnavailable => write!(f, "locked commit isn't available from the remote anymore"),
+            Problem::MissingSource { src } => write!(f, "linkfile/copyfile source `{}` doesn't exist", src.display()),
+            Problem::HashMismatch { locked, actual } => write!(f, "NAR hash mismatch, locked {locked}, got {actual}"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    /// The number of active projects checked.
+    pub checked: usize,
+    /// The problems found, by project path. Projects without problems are omitted.
+    pub failed: BTreeMap<PathBuf, Vec<Problem>>,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum VerifyLockfileError {
+    #[error("error creating git cache directory")]
+    CreateCacheDir(#[source] io::Error),
+    #[error("error resolving revision of `{0}`")]
+    LsRemote(PathBuf, #[source] GitLsRemoteError),
+    #[error("error checking commits of `{0}`")]
+    Git(PathBuf, #[source] GitError),
+    #[error("error prefetching `{0}`")]
+    NixPrefetchGit(PathBuf, #[source] NixPrefetchGitError),
+}
+
+/// Checks that the locked commit of a project can still be fetched: Either the locked revision
+/// still points to it, or it is still reachable from the revision. Commits that are locked
+/// directly need to be fetchable by their ID.
+async fn check_commit(git_cache_dir: &Path, path: &Path, entry: &LocksetEntry, lock: &Lock) -> Result<Option<Problem>, VerifyLockfileError> {
+    let repo_ref = &entry.project.repo_ref;
+    let git_error = |e| VerifyLockfileError::Git(path.to_path_buf(), e);
+    let git_dir = bare_repo(git_cache_dir, &repo_ref.repo_url).await.map_err(git_error)?;
+
+    if is_commit_id(&repo_ref.revision) {
+        return Ok(match ensure_commit(&git_dir, &repo_ref.repo_url, &lock.commit).await {
+            Ok(()) => None,
+            Err(GitError::NonzeroExitStatus(..)) => Some(Problem::CommitUnavailable),
+            Err(e) => return Err(git_error(e)),
+        });
+    }
+
+    match git_ls_remote(&repo_ref.repo_url.to_string(), &repo_ref.revision).await {
+        Ok(commit) if commit == lock.commit => return Ok(None),
+        Ok(_) => (),
+        Err(GitLsRemoteError::RevNotFound) => return Ok(Some(Problem::RevisionNotFound)),
+        Err(e) => return Err(VerifyLockfileError::LsRemote(path.to_path_buf(), e)),
+    }
+
+    let head = fetch_ref(&git_dir, &repo_ref.repo_url, &repo_ref.revision).await.map_err(git_error)?;
+    if is_ancestor(&git_dir, &lock.commit, &head).await {
+        Ok(None)
+    } else {
+        Ok(Some(Problem::CommitUnavailable))
+    }
+}
+
+/// Checks that the sources of all `linkfile`s and `copyfile`s of a project exist at the locked
+/// commit. Uses the prefetched tree if it is still in the Nix store, and the trees in the git
+/// cache otherwise.
+async fn check_sources(git_cache_dir: &Path, path: &Path, entry: &LocksetEntry, lock: &Lock) -> Result<Vec<Problem>, VerifyLockfileError> {
+    let project = &entry.project;
+    let sources: Vec<_> = project.linkfiles.iter().chain(project.copyfiles.iter()).map(|x| &x.src).collect();
+    if sources.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let git_error = |e| VerifyLockfileError::Git(path.to_path_buf(), e);
+    let in_store = !lock.path.as_os_str().is_empty() && fs::try_exists(&lock.path).await.unwrap_or(false);
+    let git_dir = if in_store {
+        None
+    } else {
+        let git_dir = bare_repo(git_cache_dir, &project.repo_ref.repo_url).await.map_err(git_error)?;
+        ensure_commit(&git_dir, &project.repo_ref.repo_url, &lock.commit).await.map_err(git_error)?;
+        Some(git_dir)
+    };
+
+    let mut problems = vec![];
+    for src in sources {
+        let exists = match &git_dir {
+            None => fs::symlink_metadata(lock.path.join(src)).await.is_ok(),
+            Some(git_dir) => {
+                let src = src.to_string_lossy();
+                !git(git_dir, &["ls-tree", &lock.commit, "--", &src]).await.map_err(git_error)?.is_empty()
+            },
+        };
+        if !exists {
+            problems.push(Problem::MissingSource { src: src.clone() });
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Checks all active projects of a lockfile against their remotes, see `check_commit` and
+/// `check_sources`. With `check_hashes`, every project is also prefetched again to compare its
+/// NAR hash to the locked one.
+pub async fn verify_lockfile(lockset: &Lockset, git_cache_dir: &Path, check_hashes: bool) -> Result<VerifyReport, VerifyLockfileError> {
+    fs::create_dir_all(git_cache_dir).await.map_err(VerifyLockfileError::CreateCacheDir)?;
+
+    let mut report = VerifyReport::default();
+    let active: Vec<_> = lockset.entries.iter().filter(|(_, x)| x.project.active).collect();
+    for (i, (path, entry)) in active.iter().enumerate() {
+        eprintln!("Verifying `{}` ({}/{})", path.display(), i+1, active.len());
+        report.checked += 1;
+        let Some(lock) = &entry.lock else {
+            report.failed.insert((*path).clone(), vec![Problem::NotLocked]);
+            continue;
+        };
+
+        let mut problems = vec![];
+        problems.extend(check_commit(git_cache_dir, path, entry, lock).await?);
+        if !problems.is_empty() {
+            // Nothing else can be checked without the commit.
+            report.failed.insert((*path).clone(), problems);
+            continue;
+        }
+        problems.extend(check_sources(git_cache_dir, path, entry, lock).await?);
+
+        if check_hashes {
+            let repo_ref = &entry.project.repo_ref;
+            let output = nix_prefetch_git(&repo_ref.repo_url, &lock.commit, repo_ref.fetch_lfs, repo_ref.fetch_submodules)
+                .await
+                .map_err(|e| VerifyLockfileError::NixPrefetchGit((*path).clone(), e))?;
+            if output.hash != lock.nix_hash {
+                problems.push(Problem::HashMismatch { locked: lock.nix_hash.clone(), actual: output.hash });
+            }
+        }
+
+        if !problems.is_empty() {
+            report.failed.insert((*path).clone(), problems);
+        }
+    }
+
+    Ok(report)
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.passed() {
+            return writeln!(f, "PASS: all {} projects verified.", self.checked);
+        }
+
+        writeln!(f, "FAIL: {} of {} projects failed verification:", self.failed.len(), self.checked)?;
+        for (path, problems) in self.failed.iter() {
+            writeln!(f, "  {}:", path.display())?;
+            for problem in problems {
+                writeln!(f, "    {problem}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use repo_manifest::resolver::LinkCopyFile;
+    use crate::test_utils::{test_project, TempDir, git as test_git, init_repo, file_url};
+
+    #[tokio::test]
+    async fn verify_projects() {
+        let tmp = TempDir::new("verify");
+        let repo = tmp.path().join("remote/build");
+        let old_commit = init_repo(&repo);
+        test_git(&repo, &["commit", "--quiet", "--allow-empty", "-m", "Update"]);
+        test_git(&repo, &["checkout", "--quiet", "-b", "rewritten", &old_commit]);
+        test_git(&repo, &["commit", "--quiet", "--allow-empty", "-m", "Force-pushed away"]);
+        let dropped_commit = test_git(&repo, &["rev-parse", "HEAD"]);
+        test_git(&repo, &["checkout", "--quiet", "main"]);
+        test_git(&repo, &["branch", "--quiet", "-D", "rewritten"]);
+
+        let url = file_url(&repo);
+        let mut projects = HashMap::from([
+            ("build/make", "refs/heads/main"),
+            ("build/soong", "refs/heads/main"),
+            ("build/gone", "refs/heads/gone"),
+            ("build/linked", "refs/heads/main"),
+        ].map(|(path, revision)| (PathBuf::from(path), test_project(path, &url, revision))));
+        projects.get_mut(Path::new("build/linked")).unwrap().linkfiles = ["README", "missing"]
+            .map(|src| LinkCopyFile { src: PathBuf::from(src), dest: PathBuf::from("dest") })
+            .to_vec();
+        let mut lockset = Lockset::new(&projects, &tmp.path().join("test.lock"));
+        for (path, commit) in [
+            ("build/make", &old_commit),
+            ("build/soong", &dropped_commit),
+            ("build/gone", &old_commit),
+            ("build/linked", &old_commit),
+        ] {
+            lockset.entries.get_mut(Path::new(path)).unwrap().lock = Some(Lock {
+                commit: commit.clone(),
+                nix_hash: String::new(),
+                path: PathBuf::new(),
+                date: 0,
+            });
+        }
+
+        let report = verify_lockfile(&lockset, &tmp.path().join("cache"), false).await.unwrap();
+        assert_eq!(report.checked, 4);
+        assert_eq!(report.failed, BTreeMap::from([
+            (PathBuf::from("build/gone"), vec![Problem::RevisionNotFound]),
+            (PathBuf::from("build/linked"), vec![Problem::MissingSource { src: PathBuf::from("missing") }]),
+            (PathBuf::from("build/soong"), vec![Problem::CommitUnavailable]),
+        ]));
+    }
+}